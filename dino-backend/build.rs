@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::prelude::*;
 use std::path::Path;
@@ -10,31 +10,41 @@ fn main() {
 
     let output_path = "./src/obstacles.rs";
     let mut file = fs::File::create(output_path).unwrap();
-    let mut enums = HashMap::new();
+    let mut enums = BTreeMap::new();
 
     let mut cactuses = vec![];
 
-    writeln!(file, "use rand::rngs::ThreadRng;").unwrap();
     writeln!(file, "use rand::Rng;").unwrap();
+    writeln!(file, "use schemars::JsonSchema;").unwrap();
     writeln!(file, "use serde::Serialize;").unwrap();
 
     writeln!(file).unwrap();
 
-    writeln!(file, "#[derive(Clone, Copy, Serialize, PartialEq, Debug)]").unwrap();
+    writeln!(
+        file,
+        "#[derive(Clone, Copy, Serialize, PartialEq, Debug, JsonSchema)]"
+    )
+    .unwrap();
+    //every sprite gets a variant, the dino's own sprites are only there for their sizes
+    writeln!(file, "#[allow(dead_code)]").unwrap();
     writeln!(file, "pub enum Obstacle {{").unwrap();
-    fs::read_dir("./sprites")
+    //sorted, `read_dir` order differs between platforms and `random_cactus` has to map the same
+    //numbers to the same cactuses everywhere for seeded maps to match
+    let mut sprites: Vec<_> = fs::read_dir("./sprites")
         .unwrap()
         .map(|f| f.unwrap().file_name())
-        .for_each(|f| {
-            let f = f.to_str().unwrap().to_owned();
-            let path = Path::new(&f);
-            let enum_name = filename_to_enum(&path.file_stem().unwrap().to_string_lossy());
-            writeln!(file, "    {},", enum_name).unwrap();
-            if enum_name.starts_with("Cactus") {
-                cactuses.push(enum_name.clone());
-            }
-            enums.insert(enum_name, f);
-        });
+        .collect();
+    sprites.sort();
+    sprites.into_iter().for_each(|f| {
+        let f = f.to_str().unwrap().to_owned();
+        let path = Path::new(&f);
+        let enum_name = filename_to_enum(&path.file_stem().unwrap().to_string_lossy());
+        writeln!(file, "    {},", enum_name).unwrap();
+        if enum_name.starts_with("Cactus") {
+            cactuses.push(enum_name.clone());
+        }
+        enums.insert(enum_name, f);
+    });
     writeln!(file, "}}").unwrap();
 
     writeln!(file).unwrap();
@@ -57,7 +67,7 @@ fn main() {
         let image_size = (image_size.0 as f32, image_size.1 as f32);
         writeln!(
             file,
-            "      Obstacle::{} => ({} as f32, {} as f32),",
+            "        Obstacle::{} => ({:?}, {:?}),",
             e,
             image_size.0 / dino_height,
             image_size.1 / dino_height
@@ -71,7 +81,7 @@ fn main() {
 
     writeln!(
         file,
-        "pub fn random_cactus(rng: &mut ThreadRng) -> Obstacle {{"
+        "pub fn random_cactus<R: Rng>(rng: &mut R) -> Obstacle {{"
     )
     .unwrap();
     writeln!(
@@ -98,7 +108,7 @@ fn main() {
 
 fn filename_to_enum(name: &str) -> String {
     name.split("-")
-        .filter(|word| word.len() != 0)
+        .filter(|word| !word.is_empty())
         .map(|word| {
             if word.chars().next().unwrap().is_alphabetic() {
                 let mut word = word.to_owned();
@@ -0,0 +1,194 @@
+//! Resume tokens, which players prove who they are with once they've joined a session.
+//!
+//! A token is `<user id>.<token id>.<expiry>.<signature>`, the signature being an HMAC-SHA256
+//! of the session id and the rest of the token. Sessions keep the token id they last issued to
+//! each player, handing out a new one revokes the old.
+//!
+//! A signer with a key of its own also makes the numbers players mustn't be able to work out
+//! ahead of time, like the daily challenge seeds.
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone, Copy)]
+pub struct TokenSigner {
+    key: [u8; 32],
+    ttl: u64, //seconds
+}
+
+/// What a valid token says about its holder.
+#[derive(PartialEq, Debug)]
+pub struct Claims {
+    pub user_id: Uuid,
+    pub token_id: u64,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum TokenError {
+    Malformed,
+    BadSignature,
+    Expired,
+}
+
+impl TokenSigner {
+    /// Signs with `secret`, or a random key if it's empty in which case tokens don't survive a
+    /// restart (sessions don't either).
+    pub fn new(secret: &str, ttl: u64) -> Self {
+        let key = if secret.is_empty() {
+            rand::random()
+        } else {
+            Sha256::digest(secret.as_bytes()).into()
+        };
+        Self { key, ttl }
+    }
+
+    pub fn issue(&self, session_id: &Uuid, user_id: &Uuid, token_id: u64) -> String {
+        let claims = format!("{}.{:016x}.{}", user_id, token_id, now() + self.ttl);
+        let signature = self.sign(session_id, &claims);
+        format!("{}.{}", claims, signature)
+    }
+
+    /// Checks that `token` was issued by this server for `session_id` and hasn't expired, it's
+    /// up to the session to check whether the token id is still the current one.
+    pub fn verify(&self, session_id: &Uuid, token: &str) -> Result<Claims, TokenError> {
+        let (claims, signature) = token.rsplit_once('.').ok_or(TokenError::Malformed)?;
+        let signature = decode_hex(signature).ok_or(TokenError::Malformed)?;
+
+        let mut mac = self.mac(session_id);
+        mac.update(claims.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| TokenError::BadSignature)?;
+
+        let mut parts = claims.split('.');
+        let (user_id, token_id, expires) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(user_id), Some(token_id), Some(expires)) => (user_id, token_id, expires),
+            _ => return Err(TokenError::Malformed),
+        };
+        let user_id = Uuid::parse_str(user_id).map_err(|_| TokenError::Malformed)?;
+        let token_id = u64::from_str_radix(token_id, 16).map_err(|_| TokenError::Malformed)?;
+        let expires: u64 = expires.parse().map_err(|_| TokenError::Malformed)?;
+
+        if now() >= expires {
+            return Err(TokenError::Expired);
+        }
+        Ok(Claims { user_id, token_id })
+    }
+
+    /// HMAC-SHA256 of `message` with the server's key.
+    pub fn keyed_hash(&self, message: &[u8]) -> [u8; 32] {
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(message);
+        mac.finalize().into_bytes().into()
+    }
+
+    fn sign(&self, session_id: &Uuid, claims: &str) -> String {
+        let mut mac = self.mac(session_id);
+        mac.update(claims.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn mac(&self, session_id: &Uuid) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        //tokens of one session are useless in any other
+        mac.update(session_id.as_bytes());
+        mac
+    }
+}
+
+/// A session password, only kept as an HMAC of it keyed with a salt of its own.
+#[derive(Clone, Copy)]
+pub struct PasswordHash {
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+impl PasswordHash {
+    pub fn new(password: &str) -> Self {
+        let salt = rand::random();
+        let hash = Self::mac(&salt, password).finalize().into_bytes().into();
+        Self { salt, hash }
+    }
+
+    /// Compares in constant time, how long it takes says nothing about the password.
+    pub fn matches(&self, password: &str) -> bool {
+        Self::mac(&self.salt, password)
+            .verify_slice(&self.hash)
+            .is_ok()
+    }
+
+    fn mac(salt: &[u8; 16], password: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(salt).unwrap();
+        mac.update(password.as_bytes());
+        mac
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[test]
+fn passwords_are_salted() {
+    let (a, b) = (PasswordHash::new("pizza"), PasswordHash::new("pizza"));
+    assert!(a.matches("pizza") && b.matches("pizza"));
+    assert!(!a.matches("pasta"));
+    assert_ne!(a.hash, b.hash);
+}
+
+#[test]
+fn tokens_are_unforgeable() {
+    let signer = TokenSigner::new("secret", 60);
+    let (session_id, user_id) = (Uuid::new_v4(), Uuid::new_v4());
+    let token = signer.issue(&session_id, &user_id, 7);
+
+    assert_eq!(
+        signer.verify(&session_id, &token),
+        Ok(Claims {
+            user_id,
+            token_id: 7
+        })
+    );
+    assert_eq!(
+        signer.verify(&Uuid::new_v4(), &token),
+        Err(TokenError::BadSignature)
+    );
+    assert_eq!(
+        TokenSigner::new("other secret", 60).verify(&session_id, &token),
+        Err(TokenError::BadSignature)
+    );
+
+    //someone else's user id with the same signature
+    let forged = token.replacen(&user_id.to_string(), &Uuid::new_v4().to_string(), 1);
+    assert_eq!(
+        signer.verify(&session_id, &forged),
+        Err(TokenError::BadSignature)
+    );
+    assert_eq!(
+        signer.verify(&session_id, &user_id.to_string()),
+        Err(TokenError::Malformed)
+    );
+
+    let expired = TokenSigner::new("secret", 0).issue(&session_id, &user_id, 7);
+    assert_eq!(
+        signer.verify(&session_id, &expired),
+        Err(TokenError::Expired)
+    );
+}
@@ -0,0 +1,266 @@
+//! Server side players for people who want opponents while nobody else is online.
+//!
+//! A bot reads the map ahead of it and sends the same game events a client would, the session
+//! replays them against the map like everyone else's, so bots crash and score the same way.
+
+use crate::map_generator::GameMap;
+use crate::math;
+use crate::obstacles::{obstacle_size, Obstacle};
+use crate::session_exec::GameEvent;
+use crate::simulation::{self, DINO_X_OFFSET, HITBOX_INSET};
+use crate::validator::SessionPhysicsConfig;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How far past its own position a bot plans its moves.
+const LOOKAHEAD: f64 = 30.0;
+
+/// Names given to bots before falling back to numbered ones.
+pub const NAMES: [&str; 8] = [
+    "Rex", "Spike", "Pebble", "Raptor", "Dot", "Bronto", "Stego", "Ptero",
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub enum BotLevel {
+    Easy,
+    Medium,
+    Hard,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BotSkill {
+    pub reaction_delay: f64, //longest a bot is late for a move, seconds
+    pub error_rate: f64,     //chance of not reacting to an obstacle at all
+}
+
+impl BotLevel {
+    pub fn skill(self) -> BotSkill {
+        let (reaction_delay, error_rate) = match self {
+            BotLevel::Easy => (0.2, 0.05),
+            BotLevel::Medium => (0.12, 0.015),
+            BotLevel::Hard => (0.06, 0.002),
+        };
+        BotSkill {
+            reaction_delay,
+            error_rate,
+        }
+    }
+}
+
+pub struct Bot {
+    skill: BotSkill,
+    next_obstacle: usize,
+    planned: Vec<GameEvent>, //by position, sent once the bot gets there
+    landing: f64,            //where the last planned jump ends
+    last_jump: Option<f64>,  //time of the last jump sent
+}
+
+impl Bot {
+    pub fn new(skill: BotSkill) -> Self {
+        Self {
+            skill,
+            next_obstacle: 0,
+            planned: vec![],
+            landing: 0.0,
+            last_jump: None,
+        }
+    }
+
+    /// Forgets the last game, for a rematch.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.skill);
+    }
+
+    /// Plans the obstacles that came into sight and returns the moves due `t` seconds into the
+    /// game.
+    pub fn tick(
+        &mut self,
+        t: f64,
+        map: &mut GameMap,
+        physics: &SessionPhysicsConfig,
+    ) -> Vec<GameEvent> {
+        let x = simulation::x_at(t, physics);
+        let map = map.obstacles_until(x + LOOKAHEAD);
+        while let Some(((obstacle_x, y), obstacles)) = map.get(self.next_obstacle) {
+            if *obstacle_x > x + LOOKAHEAD {
+                break;
+            }
+            self.next_obstacle += 1;
+            if !obstacle_x.is_finite() || rand::random::<f64>() < self.skill.error_rate {
+                continue;
+            }
+            self.plan(*obstacle_x, *y, obstacles, physics);
+        }
+
+        let due = self
+            .planned
+            .iter()
+            .take_while(|event| pos(event) <= x)
+            .count();
+        let events: Vec<GameEvent> = self.planned.drain(..due).collect();
+        if events
+            .iter()
+            .any(|event| matches!(event, GameEvent::Jump { .. }))
+        {
+            self.last_jump = Some(t);
+        }
+        events
+    }
+
+    /// Height of the bot's dino `t` seconds into the game.
+    pub fn height(&self, t: f64, physics: &SessionPhysicsConfig) -> f32 {
+        match self.last_jump {
+            Some(start) => {
+                math::jump_height_at_t((t - start) as f32, physics.jump_vel, physics.gravity)
+                    .max(0.0)
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Decides how to get past the obstacles at `(x, y)`: run under them, duck under them or
+    /// jump over them, in that order.
+    fn plan(&mut self, x: f64, y: f64, obstacles: &[Obstacle], physics: &SessionPhysicsConfig) {
+        let width: f64 = obstacles.iter().map(|o| obstacle_size(o).0 as f64).sum();
+        let height = obstacles
+            .iter()
+            .map(|o| obstacle_size(o).1 as f64)
+            .fold(0.0, f64::max);
+        let (run_width, run_height) = obstacle_size(&Obstacle::DinoRun1);
+        let (duck_width, duck_height) = obstacle_size(&Obstacle::DinoDuck1);
+
+        //hitboxes are inset on both sides, the dino fits under anything above this
+        let clears = |dino_height: f32| y >= dino_height as f64 - 2.0 * HITBOX_INSET;
+        if clears(run_height) {
+            return;
+        }
+
+        let vel = |x: f64| {
+            let (u, a) = (physics.initial_vel as f64, physics.x_acc as f64);
+            (u * u + 2.0 * a * x.max(0.0)).sqrt()
+        };
+        let late = |x: f64| vel(x) * rand::random::<f64>() * self.skill.reaction_delay;
+
+        //until the front of the ducking dino reaches the obstacle, from when its back passed it
+        let duck_start = x + 2.0 * HITBOX_INSET - DINO_X_OFFSET - duck_width as f64;
+        let duck_end = x + width - 2.0 * HITBOX_INSET - DINO_X_OFFSET;
+        if clears(duck_height) && self.landing < duck_start - 0.5 {
+            let start = duck_start - 0.5 + late(duck_start);
+            let end = duck_end + 0.2 + late(duck_end);
+            self.planned
+                .push(GameEvent::DuckStart { pos: start as f32 });
+            self.planned.push(GameEvent::DuckEnd { pos: end as f32 });
+            self.landing = self.landing.max(end);
+            self.sort_planned();
+            return;
+        }
+
+        //the dino's bottom has to stay above the obstacle's top while they overlap
+        let above = y + height - 2.0 * HITBOX_INSET + 0.01;
+        let jump_distance = math::jump_distance_c_acc(
+            vel(x) as f32,
+            physics.x_acc,
+            physics.jump_vel,
+            physics.gravity,
+        ) as f64;
+        let take_off = x - jump_distance / 2.0;
+        let (rise, fall) = math::x_above_jump_height_c_acc(
+            vel(take_off) as f32,
+            physics.x_acc,
+            above as f32,
+            physics.jump_vel,
+            physics.gravity,
+        );
+        let (rise, fall) = (rise as f64, fall as f64);
+        if !rise.is_finite() || !fall.is_finite() {
+            return; //too high to jump over, nothing to do but hope
+        }
+
+        //jumping later than `latest` runs into the obstacle, earlier than `earliest` lands on it
+        let latest = x + 2.0 * HITBOX_INSET - DINO_X_OFFSET - run_width as f64 - rise;
+        let earliest = x + width - 2.0 * HITBOX_INSET - DINO_X_OFFSET - fall;
+        let aim = if earliest <= latest {
+            earliest + (latest - earliest) * 0.25
+        } else {
+            (earliest + latest) / 2.0
+        };
+        let jump = aim.max(self.landing).max(0.0) + late(aim);
+
+        self.planned.push(GameEvent::Jump { pos: jump as f32 });
+        self.landing = jump
+            + math::jump_distance_c_acc(
+                vel(jump) as f32,
+                physics.x_acc,
+                physics.jump_vel,
+                physics.gravity,
+            ) as f64
+            + 0.05; //a jump before the dino is back on the ground is ignored
+        self.sort_planned();
+    }
+
+    fn sort_planned(&mut self) {
+        self.planned
+            .sort_by(|a, b| pos(a).partial_cmp(&pos(b)).unwrap());
+    }
+}
+
+fn pos(event: &GameEvent) -> f64 {
+    match event {
+        GameEvent::Jump { pos } | GameEvent::DuckStart { pos } | GameEvent::DuckEnd { pos } => {
+            *pos as f64
+        }
+    }
+}
+
+#[test]
+fn bots_get_past_obstacles_unless_they_make_mistakes() {
+    use crate::simulation::DinoSim;
+
+    let physics = SessionPhysicsConfig::default();
+    let run = |skill: BotSkill| {
+        let mut map = GameMap::new(
+            physics.initial_vel,
+            physics.x_acc,
+            physics.gravity,
+            physics.jump_vel,
+            42,
+        );
+        let mut bot = Bot::new(skill);
+        let mut dino = DinoSim::new();
+        let mut t = 0.0;
+        while t < 120.0 {
+            t += 0.05;
+            for event in bot.tick(t, &mut map, &physics) {
+                match event {
+                    GameEvent::Jump { pos } => {
+                        dino.jump(simulation::time_at_x(pos as f64, &physics), &physics);
+                    }
+                    GameEvent::DuckStart { pos } => {
+                        dino.duck_start(simulation::time_at_x(pos as f64, &physics))
+                    }
+                    GameEvent::DuckEnd { pos } => {
+                        dino.duck_end(simulation::time_at_x(pos as f64, &physics))
+                    }
+                }
+            }
+            let map = map.obstacles_until(simulation::x_at(t, &physics) + DINO_X_OFFSET + 2.0);
+            if let Some(dead_at) = dino.advance(t - simulation::INPUT_DELAY, map, &physics) {
+                return Some(dead_at);
+            }
+        }
+        None
+    };
+
+    //some seeds have cactus groups too wide for anyone to clear, 42 doesn't
+    let perfect = BotSkill {
+        reaction_delay: 0.0,
+        error_rate: 0.0,
+    };
+    assert_eq!(run(perfect), None);
+
+    let hopeless = BotSkill {
+        reaction_delay: 0.0,
+        error_rate: 1.0,
+    };
+    assert!(run(hopeless).is_some());
+}
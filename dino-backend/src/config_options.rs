@@ -1,18 +1,410 @@
-#[derive(Clone, Copy)]
+use crate::bot::BotLevel;
+use crate::validator::{AntiCheatAction, SessionPhysicsConfig};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use toml::{Table, Value};
+
+/// Server configuration, built up in layers: defaults, then the TOML file, then environment
+/// variables, then command line flags.
+///
+/// Every option has a dotted key (`session.max_users`) that's used as is on the command line
+/// (`--session.max_users 10`) and as `DINO_SESSION_MAX_USERS` in the environment. The file is
+/// `config.toml` unless `--config`/`DINO_CONFIG` points somewhere else.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ConfigOptions {
+    pub server: ServerConfig,
     pub session_exec: SessionExecConfig,
     pub session: SessionConfig,
+    pub matchmaking: MatchmakingConfig,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub ip: String,
+    pub port: u16,
+    pub transport: Transport,
+    pub plain_port: u16, //only used with `Transport::Both`, `port` is the TLS one then
+    pub cert_path: String,
+    pub key_path: String,
+    pub max_messages_per_second: u32, //per connection, the rest is dropped
+    pub token_key: String,            //signs resume tokens, random on every start if empty
+    pub token_ttl: u64,               //seconds
+    pub daily_key: String,            //keys daily challenge seeds, no daily challenges if empty
+    pub replay_dir: String,           //where finished games are saved, not saved if empty
+    pub match_store: String,          //file every finished match is kept in, in memory if empty
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct SessionExecConfig {
     pub max_sessions: usize,
+    pub max_playbacks: usize, //replays streamed at once, one per connection at most
     pub allow_multiple_inactive_sessions: bool,
     pub dummy_sessions: bool,
+    pub dummy_session_bots: usize, //bots waiting in every dummy session
+    pub dummy_bot_level: BotLevel,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct SessionConfig {
     pub max_users: usize,
+    pub max_spectators: usize,
     pub max_username_len: usize,
+    pub anti_cheat_action: AntiCheatAction,
+    pub anti_cheat_tolerance: u32, //violations before `anti_cheat_action` is taken
+    pub countdown: u64,            //seconds
+    pub max_duration: u64,         //seconds
+    pub map_chunk_size: usize,     //obstacles sent per map request
+    pub reconnect_grace: u64,      //seconds a dropped player has to log back in
+    pub rematch_window: u64,       //seconds to vote for a rematch after the game, 0 to close
+    pub elimination: bool,         //the game ends once a single player is left running
+    pub physics: SessionPhysicsConfig,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchmakingConfig {
+    pub target_size: usize, //players in a quick play session
+    pub min_size: usize,    //players needed to start one short of `target_size`
+    pub max_wait: u64,      //seconds before starting short of `target_size`
+    pub rating_bucket: f64, //width of the rating ranges players are matched within, 0 to ignore
+}
+
+/// Which listeners the server opens, `Both` serves plain connections on a port of their own.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Transport {
+    #[serde(rename = "ws")]
+    Plain,
+    #[serde(rename = "wss")]
+    Tls,
+    #[serde(rename = "both")]
+    Both,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            ip: "127.0.0.1".to_owned(),
+            port: 8080,
+            transport: Transport::Tls,
+            plain_port: 8081,
+            cert_path: "../../certs/cert.pem".to_owned(),
+            key_path: "../../certs/key.pem".to_owned(),
+            max_messages_per_second: 240,
+            token_key: String::new(),
+            daily_key: String::new(),
+            token_ttl: 6 * 60 * 60,
+            replay_dir: String::new(),
+            match_store: String::new(),
+        }
+    }
+}
+
+impl Default for SessionExecConfig {
+    fn default() -> Self {
+        Self {
+            max_sessions: 10,
+            max_playbacks: 20,
+            allow_multiple_inactive_sessions: true,
+            dummy_sessions: false,
+            dummy_session_bots: 0,
+            dummy_bot_level: BotLevel::Medium,
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            max_users: 20,
+            max_spectators: 10,
+            max_username_len: 15,
+            anti_cheat_action: AntiCheatAction::Flag,
+            anti_cheat_tolerance: 3,
+            countdown: 3,
+            max_duration: 30 * 60,
+            map_chunk_size: 100,
+            reconnect_grace: 10,
+            rematch_window: 30,
+            elimination: false,
+            physics: SessionPhysicsConfig::default(),
+        }
+    }
+}
+
+impl Default for MatchmakingConfig {
+    fn default() -> Self {
+        Self {
+            target_size: 4,
+            min_size: 2,
+            max_wait: 20,
+            rating_bucket: 0.0,
+        }
+    }
+}
+
+/// Environment variables from before the config file, applied under the `DINO_` ones.
+const LEGACY_ENV_VARS: [(&str, &str); 6] = [
+    ("IP_ADDR", "server.ip"),
+    ("PORT", "server.port"),
+    ("TRANSPORT", "server.transport"),
+    ("WS_PORT", "server.plain_port"),
+    ("SSL_CERT_PATH", "server.cert_path"),
+    ("SSL_KEY_PATH", "server.key_path"),
+];
+
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+impl ConfigOptions {
+    /// Loads the configuration from every layer, `args` being the command line arguments
+    /// without the program name.
+    ///
+    /// Returns `Ok(None)` if `--help` was passed, after printing every option.
+    pub fn load<I: Iterator<Item = String>>(args: I) -> Result<Option<Self>, String> {
+        let defaults = Value::try_from(Self::default()).map_err(|err| err.to_string())?;
+        let mut keys = vec![];
+        flatten(defaults.as_table().unwrap(), "", &mut keys);
+
+        let mut config_path = std::env::var("DINO_CONFIG").ok();
+        let mut flags = vec![];
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                println!("Options (--key value, or DINO_KEY=value in the environment):");
+                for (key, default) in &keys {
+                    println!("  --{} (default: {})", key, default);
+                }
+                return Ok(None);
+            }
+
+            let flag = arg
+                .strip_prefix("--")
+                .ok_or_else(|| format!("unexpected argument `{}`", arg))?;
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (key.to_owned(), value.to_owned()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing value for `--{}`", flag))?;
+                    (flag.to_owned(), value)
+                }
+            };
+
+            if key == "config" {
+                config_path = Some(value);
+            } else {
+                flags.push((key, value));
+            }
+        }
+
+        let mut table = match config_path {
+            Some(path) => read_config_file(Path::new(&path))?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                read_config_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Table::new(),
+        };
+
+        for (var, key) in LEGACY_ENV_VARS {
+            if let Ok(value) = std::env::var(var) {
+                set_key(&mut table, &keys, key, &value)
+                    .map_err(|err| format!("{}: {}", var, err))?;
+            }
+        }
+        for (key, _) in &keys {
+            let var = format!("DINO_{}", key.replace('.', "_").to_uppercase());
+            if let Ok(value) = std::env::var(&var) {
+                set_key(&mut table, &keys, key, &value)
+                    .map_err(|err| format!("{}: {}", var, err))?;
+            }
+        }
+        for (key, value) in flags {
+            set_key(&mut table, &keys, &key, &value)
+                .map_err(|err| format!("--{}: {}", key, err))?;
+        }
+
+        let config: Self = Value::Table(table)
+            .try_into()
+            .map_err(|err: toml::de::Error| err.to_string())?;
+        config.validate()?;
+        Ok(Some(config))
+    }
+
+    /// Checks for values that would make the server misbehave, reporting all of them at once.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = vec![];
+        let mut check = |ok: bool, err: &str| {
+            if !ok {
+                errors.push(err.to_owned());
+            }
+        };
+
+        let server = &self.server;
+        check(
+            server.transport != Transport::Both || server.port != server.plain_port,
+            "server.port and server.plain_port must differ when serving both ws and wss",
+        );
+
+        check(
+            server.max_messages_per_second > 0,
+            "server.max_messages_per_second must be at least 1",
+        );
+        check(server.token_ttl > 0, "server.token_ttl must be at least 1");
+
+        let session_exec = &self.session_exec;
+        check(
+            session_exec.max_sessions > 0,
+            "session_exec.max_sessions must be at least 1",
+        );
+
+        let session = &self.session;
+        check(
+            session.max_users > 0,
+            "session.max_users must be at least 1",
+        );
+        check(
+            session.max_username_len > 0,
+            "session.max_username_len must be at least 1",
+        );
+        check(
+            session.anti_cheat_tolerance > 0,
+            "session.anti_cheat_tolerance must be at least 1",
+        );
+        check(
+            session.max_duration > 0,
+            "session.max_duration must be at least 1",
+        );
+        check(
+            (1..=1000).contains(&session.map_chunk_size),
+            "session.map_chunk_size must be between 1 and 1000",
+        );
+
+        let matchmaking = &self.matchmaking;
+        check(
+            matchmaking.min_size > 0,
+            "matchmaking.min_size must be at least 1",
+        );
+        check(
+            matchmaking.min_size <= matchmaking.target_size,
+            "matchmaking.min_size can't be more than matchmaking.target_size",
+        );
+        check(
+            matchmaking.target_size <= session.max_users,
+            "matchmaking.target_size can't be more than session.max_users",
+        );
+        check(
+            matchmaking.rating_bucket.is_finite() && matchmaking.rating_bucket >= 0.0,
+            "matchmaking.rating_bucket can't be negative",
+        );
+
+        //the same limits hosts get for their own sessions
+        if let Err(err) = session.physics.check_bounds() {
+            check(false, &format!("session.physics: {}", err));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+fn read_config_file(path: &Path) -> Result<Table, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read `{}`: {}", path.display(), err))?;
+    contents
+        .parse::<Table>()
+        .map_err(|err| format!("failed to parse `{}`: {}", path.display(), err))
+}
+
+/// Collects the dotted key and value of every option in `table`.
+fn flatten(table: &Table, prefix: &str, out: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let key = format!("{}{}", prefix, key);
+        match value {
+            Value::Table(table) => flatten(table, &format!("{}.", key), out),
+            value => out.push((key, value.clone())),
+        }
+    }
+}
+
+/// Sets the dotted `key` in `table`, parsing `value` as the type of the option's default.
+fn set_key(
+    table: &mut Table,
+    keys: &[(String, Value)],
+    key: &str,
+    value: &str,
+) -> Result<(), String> {
+    let default = keys
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, default)| default)
+        .ok_or_else(|| format!("unknown option `{}`", key))?;
+
+    let value = match default {
+        Value::Integer(_) => value
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| format!("`{}` isn't an integer", value))?,
+        Value::Float(_) => value
+            .parse()
+            .map(Value::Float)
+            .map_err(|_| format!("`{}` isn't a number", value))?,
+        Value::Boolean(_) => value
+            .parse()
+            .map(Value::Boolean)
+            .map_err(|_| format!("`{}` isn't `true` or `false`", value))?,
+        _ => Value::String(value.to_owned()),
+    };
+
+    let mut path: Vec<&str> = key.split('.').collect();
+    let last = path.pop().unwrap();
+    let mut table = table;
+    for section in path {
+        table = table
+            .entry(section)
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("`{}` isn't a table in the config file", section))?;
+    }
+    table.insert(last.to_owned(), value);
+    Ok(())
+}
+
+#[test]
+fn layers_override_each_other() {
+    let defaults = Value::try_from(ConfigOptions::default()).unwrap();
+    let mut keys = vec![];
+    flatten(defaults.as_table().unwrap(), "", &mut keys);
+
+    let mut table: Table = "[session]\nmax_users = 4\n[session.physics]\ngravity = -40.0"
+        .parse()
+        .unwrap();
+    set_key(&mut table, &keys, "session.max_users", "6").unwrap();
+    set_key(&mut table, &keys, "session.physics.jump_vel", "12").unwrap();
+    set_key(&mut table, &keys, "server.transport", "both").unwrap();
+    assert!(set_key(&mut table, &keys, "session.max_users", "many").is_err());
+    assert!(set_key(&mut table, &keys, "session.max_user", "6").is_err());
+
+    let config: ConfigOptions = Value::Table(table).try_into().unwrap();
+    assert_eq!(config.session.max_users, 6);
+    assert_eq!(config.session.max_username_len, 15);
+    assert_eq!(config.session.physics.gravity, -40.0);
+    assert_eq!(config.session.physics.jump_vel, 12.0);
+    assert_eq!(config.server.transport, Transport::Both);
+
+    assert!(config.validate().is_ok());
+    let mut invalid = config.clone();
+    invalid.session.physics.gravity = 10.0;
+    invalid.server.plain_port = invalid.server.port;
+    assert_eq!(invalid.validate().unwrap_err().lines().count(), 2);
+
+    //jumps this low can't clear the tallest cactus
+    let mut invalid = config;
+    invalid.session.physics.jump_vel = 6.0;
+    assert!(invalid.validate().unwrap_err().contains("tallest cactus"));
 }
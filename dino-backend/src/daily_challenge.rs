@@ -0,0 +1,148 @@
+//! The daily challenge: a map derived from the UTC date that everyone plays on their own, with
+//! a leaderboard per day.
+
+use crate::auth::TokenSigner;
+use rustc_hash::FxHashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds a daily challenge session waits for its player to launch the game.
+pub const WAIT_TIME: u64 = 10;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Days since the unix epoch in UTC.
+pub fn today() -> u32 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    (secs / SECS_PER_DAY) as u32
+}
+
+/// Map seed of `day`, keyed with `server.daily_key` so the maps of the days to come can't be
+/// played ahead of time. The key has to be configured, a random one would change the map on
+/// every restart.
+pub fn seed(day: u32, keys: &TokenSigner) -> u32 {
+    let hash = keys.keyed_hash(format!("daily challenge {}", day).as_bytes());
+    u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]])
+}
+
+/// `YYYY-MM-DD` of `day`.
+pub fn format_date(day: u32) -> String {
+    let (y, m, d) = civil_from_days(day as i64);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+/// Inverse of `format_date`, `None` if `date` isn't a valid date after the epoch.
+pub fn parse_date(date: &str) -> Option<u32> {
+    let mut parts = date.split('-');
+    let y: i64 = parts.next()?.parse().ok()?;
+    let m: i64 = parts.next()?.parse().ok()?;
+    let d: i64 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
+    }
+
+    let day = days_from_civil(y, m, d);
+    //days past the end of the month roll over into the next one
+    if day < 0 || day > u32::MAX as i64 || civil_from_days(day) != (y, m, d) {
+        return None;
+    }
+    Some(day as u32)
+}
+
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400;
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+/// Best score of every player, per day.
+#[derive(Default)]
+pub struct DailyLeaderboards {
+    days: FxHashMap<u32, FxHashMap<String, u64>>,
+}
+
+impl DailyLeaderboards {
+    pub fn record(&mut self, day: u32, username: &str, score: u64) {
+        let best = self
+            .days
+            .entry(day)
+            .or_default()
+            .entry(username.to_owned())
+            .or_insert(0);
+        *best = score.max(*best);
+    }
+
+    /// Highest score first.
+    pub fn scores(&self, day: u32) -> Vec<(String, u64)> {
+        let mut scores: Vec<(String, u64)> = self
+            .days
+            .get(&day)
+            .map(|scores| scores.iter().map(|(u, s)| (u.clone(), *s)).collect())
+            .unwrap_or_default();
+
+        scores.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scores
+    }
+}
+
+#[test]
+fn dates() {
+    assert_eq!(format_date(0), "1970-01-01");
+    assert_eq!(format_date(19782), "2024-02-29");
+    assert_eq!(parse_date("2024-02-29"), Some(19782));
+    assert_eq!(parse_date("2023-02-29"), None);
+    assert_eq!(parse_date("1969-12-31"), None);
+    assert_eq!(parse_date("2024-13-01"), None);
+    assert_eq!(parse_date("today"), None);
+    assert_eq!(parse_date(&format_date(today())), Some(today()));
+}
+
+#[test]
+fn seeds_depend_on_the_key() {
+    let keys = TokenSigner::new("secret", 60);
+    assert_eq!(seed(19782, &keys), seed(19782, &keys));
+    assert_ne!(seed(19782, &keys), seed(19783, &keys));
+    assert_ne!(
+        seed(19782, &keys),
+        seed(19782, &TokenSigner::new("other secret", 60))
+    );
+}
+
+#[test]
+fn keeps_best_score_per_player() {
+    let mut leaderboards = DailyLeaderboards::default();
+    leaderboards.record(1, "a", 50);
+    leaderboards.record(1, "b", 80);
+    leaderboards.record(1, "a", 30);
+    leaderboards.record(1, "c", 80);
+    leaderboards.record(2, "a", 10);
+
+    assert_eq!(
+        leaderboards.scores(1),
+        vec![
+            ("b".to_owned(), 80),
+            ("c".to_owned(), 80),
+            ("a".to_owned(), 50)
+        ]
+    );
+    assert!(leaderboards.scores(3).is_empty());
+}
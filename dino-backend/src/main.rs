@@ -1,46 +1,73 @@
+mod auth;
+mod bot;
 mod config_options;
+mod daily_challenge;
 mod map_generator;
+mod matchmaking;
 mod math;
 mod obstacles;
+mod protocol_schema;
+mod rating;
+mod replay;
 mod session;
 mod session_exec;
+mod simulation;
+mod storage;
 mod validator;
-use std::{
-    io::{Error as IoError, ErrorKind},
-    net::SocketAddr,
-    sync::{Arc, Mutex},
-};
+use std::time::{Duration, Instant};
+use std::{io::Error as IoError, net::SocketAddr, sync::Arc};
 use tokio_rustls::rustls::{self, Certificate, PrivateKey};
 use tokio_rustls::TlsAcceptor;
 
-use config_options::{ConfigOptions, SessionConfig, SessionExecConfig};
+use config_options::{ConfigOptions, Transport};
 
 use futures_channel::mpsc::unbounded;
-use futures_util::{future, pin_mut, stream::TryStreamExt, StreamExt};
+use futures_util::{future, pin_mut, stream, stream::TryStreamExt, StreamExt};
 
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
 use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite::protocol::Message;
 
 use crate::session_exec::ChannelData;
 use crate::session_exec::SessionExecutor;
+use crate::session_exec::{ErrorCode, TxData};
 
-type Tx = mpsc::Sender<Message>;
-type SessionExecSync = Arc<Mutex<SessionExecutor>>;
+use rustls_pemfile::certs;
 
-use rustls_pemfile::{certs, rsa_private_keys};
-
-const MESSAGE_CHANNEL_CAPACITY: usize = 2048;
-
-async fn handle_connection(
+/// Accepts connections until the listener fails, doing the TLS handshake first if there's an
+/// `acceptor`.
+async fn accept_connections(
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
     session_channel: mpsc::Sender<ChannelData>,
-    acceptor: TlsAcceptor,
-    raw_stream: TcpStream,
-    addr: SocketAddr,
+    max_rate: u32,
 ) {
-    println!("Incoming TCP connection from: {}", addr);
+    while let Ok((stream, addr)) = listener.accept().await {
+        println!("Incoming TCP connection from: {}", addr);
+        let session_channel = session_channel.clone();
+        match acceptor.clone() {
+            Some(acceptor) => tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => handle_connection(session_channel, stream, addr, max_rate).await,
+                    Err(err) => println!("TLS handshake with `{}` failed: {}", addr, err),
+                }
+            }),
+            None => tokio::spawn(handle_connection(session_channel, stream, addr, max_rate)),
+        };
+    }
+}
 
-    let stream = acceptor.accept(raw_stream).await.expect("failed to create tls stream");
+/// Passes messages between `stream` and the executor, dropping incoming ones above `max_rate`
+/// a second.
+async fn handle_connection<S>(
+    session_channel: mpsc::Sender<ChannelData>,
+    stream: S,
+    addr: SocketAddr,
+    max_rate: u32,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     let ws_stream = tokio_tungstenite::accept_async(stream)
         .await
         .expect("Error during the websocket handshake occurred");
@@ -53,6 +80,8 @@ async fn handle_connection(
     // Insert the write part of this peer to the peer map.
     let (transmitter_tx, transmitter_rx) = unbounded(); //from the perspective of `Session`
     let (receiver_tx, receiver_rx) = unbounded();
+    //for messages that never make it to the executor
+    let (error_tx, error_rx) = unbounded();
     // peer_map.lock().unwrap().insert(addr, tx);
 
     match session_channel
@@ -75,33 +104,57 @@ async fn handle_connection(
 
     let (outgoing, incoming) = ws_stream.split();
 
+    let mut window = (Instant::now(), 0); //start of the current second, messages received in it
     let broadcast_incoming = incoming.try_for_each(|msg| {
-        #[cfg(debug)]
-        println!(
-            "Received a message from {}: {}",
-            addr,
-            msg.to_text().unwrap()
-        );
         // session_channel.send(ChannelData::Message(msg));
         use session_exec::RxData;
         if let Ok(msg) = msg.to_text() {
+            if window.0.elapsed() >= Duration::from_secs(1) {
+                window = (Instant::now(), 0);
+            }
+            window.1 += 1;
+            if window.1 > max_rate {
+                //only the first dropped message of every second is answered
+                if window.1 == max_rate + 1 {
+                    let _ = error_tx.unbounded_send(TxData::Error {
+                        code: ErrorCode::RateLimited,
+                        request: request_type(msg),
+                    });
+                }
+                return future::ok(());
+            }
+
             match serde_json::from_str::<RxData>(msg) {
-                Ok(msg) => match receiver_tx.unbounded_send(msg) {
-                    Err(err) => println!("Failed sending message to session executor: {}", err),
-                    _ => (),
-                },
+                Ok(msg) => {
+                    if let Err(err) = receiver_tx.unbounded_send(msg) {
+                        println!("Failed sending message to session executor: {}", err)
+                    }
+                }
                 Err(err) => {
                     #[cfg(debug_assertions)]
-                    println!("Error parsing incoming message: {err:?}")
+                    println!("Error parsing incoming message: {err:?}");
+                    let _ = error_tx.unbounded_send(TxData::Error {
+                        code: ErrorCode::MalformedMessage,
+                        request: request_type(msg),
+                    });
                 }
             }
         }
         future::ok(())
     });
 
-    let recv_from_session_exec = transmitter_rx
-        .map(|tx_data| Ok(Message::Text(serde_json::to_string(&tx_data).unwrap())))
-        .forward(outgoing);
+    #[allow(clippy::result_large_err)] //the error type is dictated by the websocket sink
+    let recv_from_session_exec = stream::select(
+        //the connection is closed once the session side hangs up, errors don't keep it open
+        transmitter_rx
+            .map(Some)
+            .chain(stream::once(future::ready(None))),
+        error_rx.map(Some),
+    )
+    .take_while(|tx_data| future::ready(tx_data.is_some()))
+    .filter_map(future::ready)
+    .map(|tx_data: TxData| Ok(Message::Text(serde_json::to_string(&tx_data).unwrap())))
+    .forward(outgoing);
 
     pin_mut!(broadcast_incoming, recv_from_session_exec);
     future::select(broadcast_incoming, recv_from_session_exec).await;
@@ -120,6 +173,12 @@ async fn handle_connection(
     // peer_map.lock().unwrap().remove(&addr);
 }
 
+/// The `type` of a message that may not parse as `RxData`.
+fn request_type(msg: &str) -> Option<String> {
+    let msg: serde_json::Value = serde_json::from_str(msg).ok()?;
+    msg["type"].as_str().map(str::to_owned)
+}
+
 use std::fs::File;
 use std::io::{self, BufReader};
 use std::path::Path;
@@ -138,63 +197,98 @@ fn load_keys(path: &Path) -> io::Result<Vec<PrivateKey>> {
 
 #[tokio::main]
 async fn main() -> Result<(), IoError> {
-    let port = std::env::var("PORT")
-        .unwrap_or("8080".to_string())
-        .parse::<usize>();
-    let ip = std::env::var("IP_ADDR").unwrap_or("127.0.0.1".to_string());
-    let addr = &format!("{}:{}", ip, port.unwrap_or(8080));
-    let server_config = ConfigOptions {
-        session: SessionConfig {
-            max_users: 20,
-            max_username_len: 15,
-        },
-        session_exec: SessionExecConfig {
-            max_sessions: 10,
-            allow_multiple_inactive_sessions: true,
-            dummy_sessions: false,
-        },
-    };
+    match std::env::args().nth(1).as_deref() {
+        Some("--emit-schema") => {
+            print!("{}", protocol_schema::json_schema_string());
+            return Ok(());
+        }
+        Some("--emit-typescript") => {
+            print!("{}", protocol_schema::typescript());
+            return Ok(());
+        }
+        _ => (),
+    }
 
-    let cert_path = std::env::var("SSL_CERT_PATH").unwrap_or("../../certs/cert.pem".to_owned());
-    let cert_path = Path::new(&cert_path);
-    let key_path = std::env::var("SSL_KEY_PATH").unwrap_or("../../certs/key.pem".to_owned());
-    let key_path = Path::new(&key_path);
+    let server_config = match ConfigOptions::load(std::env::args().skip(1)) {
+        Ok(Some(config)) => config,
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            eprintln!("Invalid configuration:\n{}", err);
+            std::process::exit(1);
+        }
+    };
+    let server = server_config.server.clone();
+    let addr = &format!("{}:{}", server.ip, server.port);
+    let plain_addr = &format!("{}:{}", server.ip, server.plain_port);
 
-    let certs = load_certs(cert_path)?;
-    let mut keys = load_keys(key_path)?;
+    let (tls_addr, plain_addr) = match server.transport {
+        Transport::Plain => (None, Some(addr)),
+        Transport::Tls => (Some(addr), None),
+        Transport::Both => (Some(addr), Some(plain_addr)),
+    };
 
+    let mut listeners = vec![];
+    if let Some(addr) = tls_addr {
+        let certs = load_certs(Path::new(&server.cert_path))?;
+        let mut keys = load_keys(Path::new(&server.key_path))?;
+        if keys.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no private key found",
+            ));
+        }
 
+        let tls_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, keys.remove(0))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
 
-    let tls_config = rustls::ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth()
-        .with_single_cert(certs, keys.remove(0))
-        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
-    let acceptor = TlsAcceptor::from(Arc::new(tls_config));
-    // let state = PeerMap::new(Mutex::new(HashMap::new()));
-    let try_socket = TcpListener::bind(addr).await;
-    let listener = try_socket.expect("Failed to bind");
-    println!("Listening on: {}", addr);
+        let listener = TcpListener::bind(addr).await.expect("Failed to bind");
+        println!("Listening on: wss://{}", addr);
+        listeners.push((listener, Some(acceptor)));
+    }
+    if let Some(addr) = plain_addr {
+        let listener = TcpListener::bind(addr).await.expect("Failed to bind");
+        println!("Listening on: ws://{}", addr);
+        listeners.push((listener, None));
+    }
 
     //FIXME: not sure what the buffer size should be;
     let session_exec_channel = mpsc::channel(2048);
     let (session_tx, session_rx) = session_exec_channel;
-    let session_exec_thread = tokio::task::spawn_blocking(move || {
-        let mut session_exec = SessionExecutor::new_with_channel(session_rx, server_config);
-        loop {
-            session_exec.poll_main_channel();
-            session_exec.poll_sub_channels();
-            session_exec.run();
+
+    //sessions aren't `Send`, so the executor lives on this thread next to the accept loop
+    let matches = match storage::open(&server_config.server.match_store) {
+        Ok(matches) => matches,
+        Err(err) => {
+            eprintln!(
+                "Failed to open the match store `{}`: {}",
+                server_config.server.match_store, err
+            );
+            std::process::exit(1);
         }
-    });
+    };
+    let session_exec = SessionExecutor::new_with_channel(session_rx, server_config, matches);
+    let local = tokio::task::LocalSet::new();
+    let session_exec_task = local.spawn_local(session_exec.run());
 
-    while let Ok((stream, addr)) = listener.accept().await {
-        let acceptor = acceptor.clone();
-        tokio::spawn(handle_connection(session_tx.clone(), acceptor, stream, addr));
-           
-    }
+    local
+        .run_until(future::join_all(listeners.into_iter().map(
+            |(listener, acceptor)| {
+                accept_connections(
+                    listener,
+                    acceptor,
+                    session_tx.clone(),
+                    server.max_messages_per_second,
+                )
+            },
+        )))
+        .await;
 
-    futures_util::join!(session_exec_thread);
+    drop(local);
+    let _ = session_exec_task.await;
 
     Ok(())
 }
@@ -3,7 +3,11 @@ use crate::math;
 use crate::obstacles::Obstacle;
 use crate::obstacles::{obstacle_size, random_cactus, TALLEST_CACTUS};
 use rand::prelude::*;
-use rand::rngs::ThreadRng;
+use rand_chacha::ChaCha8Rng;
+
+/// ChaCha's output is specified bit for bit, unlike `StdRng`'s which may change between rand
+/// releases, so a seed keeps producing the same map.
+pub type MapRng = ChaCha8Rng;
 
 pub struct GameMap {
     map: Vec<((f64, f64), Vec<Obstacle>)>,
@@ -12,15 +16,20 @@ pub struct GameMap {
     acc: f32,
     g: f32,
     jump_vel: f32,
-    rng: ThreadRng,
+    rng: MapRng,
+    seed: u32,
 }
 
 impl GameMap {
-    pub fn new(initial_x_vel: f32, x_acc: f32, gravity: f32, jump_vel: f32) -> Self {
+    /// Maps generated with the same seed and physics are identical, on every platform too: the
+    /// obstacles are placed with nothing but arithmetic and `sqrt`, which IEEE 754 rounds the
+    /// same everywhere unlike `powi` and the like.
+    pub fn new(initial_x_vel: f32, x_acc: f32, gravity: f32, jump_vel: f32, seed: u32) -> Self {
         Self {
             map: vec![],
             pos: 4.0, //initial padding
-            rng: thread_rng(),
+            rng: MapRng::seed_from_u64(seed as u64),
+            seed,
             u: initial_x_vel,
             acc: x_acc,
             g: gravity,
@@ -28,9 +37,13 @@ impl GameMap {
         }
     }
 
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
     fn vel_at_pos(&self, x: f64) -> f32 {
         //v^2 = u^2 + 2as
-        (self.u.powi(2) + 2.0 * self.acc * x as f32).sqrt() as f32
+        (self.u * self.u + 2.0 * self.acc * x as f32).sqrt()
     }
 
     fn random_cactus(&mut self) -> Obstacle {
@@ -146,6 +159,20 @@ impl GameMap {
         group
     }
 
+    /// Every obstacle generated so far.
+    pub fn generated(&self) -> &[((f64, f64), Vec<Obstacle>)] {
+        &self.map
+    }
+
+    /// Every obstacle generated so far, extending the map until it reaches past `x`.
+    pub fn obstacles_until(&mut self, x: f64) -> &[((f64, f64), Vec<Obstacle>)] {
+        while self.pos <= x {
+            self.gen_map(100);
+        }
+
+        &self.map
+    }
+
     pub fn get_map(&mut self, from: usize, to: usize) -> &[((f64, f64), Vec<Obstacle>)] {
         if to >= self.map.len() {
             self.gen_map(to + 1 - self.map.len())
@@ -154,3 +181,21 @@ impl GameMap {
         &self.map[from..to + 1]
     }
 }
+
+#[test]
+fn seeded_maps_are_reproducible() {
+    let map = |seed| {
+        let mut map = GameMap::new(8.0, 0.3, -60.0, 15.0, seed);
+        serde_json::to_string(map.get_map(0, 299)).unwrap()
+    };
+
+    assert_eq!(map(42), map(42));
+    assert_ne!(map(42), map(43));
+
+    //pinned so a change in the generator, rng or sprite order that breaks old seeds is noticed
+    let mut map = GameMap::new(8.0, 0.3, -60.0, 15.0, 42);
+    assert_eq!(
+        serde_json::to_string(map.get_map(0, 3)).unwrap(),
+        r#"[[[20.713143825531006,0.9914373159408569],["Bird1"]],[[25.655189037322998,0.7053603529930115],["Bird1"]],[[39.07040190696716,0.0],["CactusSmall2","CactusSmall6"]],[[46.450135231018066,0.0],["CactusBigSmall"]]]"#
+    );
+}
@@ -0,0 +1,143 @@
+//! Quick play: connections wait in a queue and get grouped into new sessions instead of picking
+//! a lobby themselves.
+
+use crate::config_options::MatchmakingConfig;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+pub struct QueuedPlayer {
+    pub addr: SocketAddr,
+    pub username: String,
+    pub rating: Option<f64>,
+    since: Instant,
+}
+
+/// Players waiting for a quick play match, oldest first.
+#[derive(Default)]
+pub struct Matchmaker {
+    queue: Vec<QueuedPlayer>,
+}
+
+impl Matchmaker {
+    pub fn enqueue(&mut self, addr: SocketAddr, username: String, rating: Option<f64>) {
+        self.queue.push(QueuedPlayer {
+            addr,
+            username,
+            rating,
+            since: Instant::now(),
+        });
+    }
+
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.queue.iter().any(|player| &player.addr == addr)
+    }
+
+    pub fn remove(&mut self, addr: &SocketAddr) {
+        self.queue.retain(|player| &player.addr != addr);
+    }
+
+    /// Drops everyone `keep` returns false for, like connections that closed or joined a
+    /// session on their own.
+    pub fn retain(&mut self, mut keep: impl FnMut(&SocketAddr) -> bool) {
+        self.queue.retain(|player| keep(&player.addr));
+    }
+
+    /// When the next group that's big enough stops waiting for more players.
+    pub fn next_deadline(&self, config: &MatchmakingConfig) -> Option<Instant> {
+        self.groups(config)
+            .into_iter()
+            .filter(|group| group.len() >= config.min_size)
+            .map(|group| self.queue[group[0]].since + Duration::from_secs(config.max_wait))
+            .min()
+    }
+
+    /// Takes the next group out of the queue: `target_size` players of the same rating bucket,
+    /// or at least `min_size` once the longest waiting of them has waited `max_wait` seconds.
+    pub fn next_match(
+        &mut self,
+        config: &MatchmakingConfig,
+        now: Instant,
+    ) -> Option<Vec<QueuedPlayer>> {
+        let max_wait = Duration::from_secs(config.max_wait);
+        let mut group = self.groups(config).into_iter().find(|group| {
+            group.len() >= config.target_size
+                || (group.len() >= config.min_size && now >= self.queue[group[0]].since + max_wait)
+        })?;
+        group.truncate(config.target_size);
+
+        let mut players = vec![];
+        for i in group.into_iter().rev() {
+            players.push(self.queue.remove(i));
+        }
+        players.reverse();
+        Some(players)
+    }
+
+    /// Indices of the players in each rating bucket, oldest first. Players whose username is
+    /// already in the bucket wait for the next one.
+    fn groups(&self, config: &MatchmakingConfig) -> Vec<Vec<usize>> {
+        let bucket = |player: &QueuedPlayer| match player.rating {
+            Some(rating) if config.rating_bucket > 0.0 => {
+                Some((rating / config.rating_bucket).floor() as i64)
+            }
+            _ => None,
+        };
+
+        let mut groups: Vec<(Option<i64>, Vec<usize>)> = vec![];
+        for (i, player) in self.queue.iter().enumerate() {
+            let key = bucket(player);
+            let taken = |group: &[usize]| {
+                group
+                    .iter()
+                    .any(|&j| self.queue[j].username == player.username)
+            };
+            match groups
+                .iter_mut()
+                .find(|(other, group)| *other == key && !taken(group))
+            {
+                Some((_, group)) => group.push(i),
+                None => groups.push((key, vec![i])),
+            }
+        }
+        groups.into_iter().map(|(_, group)| group).collect()
+    }
+}
+
+#[test]
+fn players_are_grouped_by_size_and_wait() {
+    let config = MatchmakingConfig {
+        target_size: 3,
+        min_size: 2,
+        max_wait: 20,
+        rating_bucket: 100.0,
+    };
+    let addr = |port: u16| SocketAddr::from(([127, 0, 0, 1], port));
+    let mut matchmaker = Matchmaker::default();
+
+    matchmaker.enqueue(addr(1), "a".to_owned(), Some(1010.0));
+    matchmaker.enqueue(addr(2), "b".to_owned(), Some(1500.0));
+    matchmaker.enqueue(addr(3), "c".to_owned(), Some(1090.0));
+    let now = Instant::now();
+    assert!(matchmaker.next_match(&config, now).is_none());
+
+    matchmaker.enqueue(addr(4), "c".to_owned(), Some(1050.0));
+    assert!(
+        matchmaker.next_match(&config, now).is_none(),
+        "usernames are unique within a match"
+    );
+
+    matchmaker.enqueue(addr(5), "d".to_owned(), Some(1000.0));
+    let players = matchmaker.next_match(&config, now).unwrap();
+    let ports: Vec<u16> = players.iter().map(|player| player.addr.port()).collect();
+    assert_eq!(ports, [1, 3, 5]);
+
+    //only `b` is left in their bucket, the other `c` gets a match once they waited long enough
+    matchmaker.enqueue(addr(6), "e".to_owned(), Some(1001.0));
+    assert!(matchmaker.next_match(&config, now).is_none());
+    let later = now + Duration::from_secs(config.max_wait + 1);
+    assert!(matchmaker.next_deadline(&config).unwrap() < later);
+    let players = matchmaker.next_match(&config, later).unwrap();
+    let ports: Vec<u16> = players.iter().map(|player| player.addr.port()).collect();
+    assert_eq!(ports, [4, 6]);
+    assert!(matchmaker.next_match(&config, later).is_none());
+}
@@ -1,70 +1,71 @@
-use rand::rngs::ThreadRng;
 use rand::Rng;
+use schemars::JsonSchema;
 use serde::Serialize;
 
-#[derive(Clone, Copy, Serialize, PartialEq, Debug)]
+#[derive(Clone, Copy, Serialize, PartialEq, Debug, JsonSchema)]
+#[allow(dead_code)] //every sprite the frontend has, only some of them are obstacles the server places
 pub enum Obstacle {
+    Bird1,
     Bird2,
-    CactusSmall5,
-    DinoGameOver1,
-    Ground,
     CactusBig1,
-    DinoDuck2,
     CactusBig2,
+    CactusBigPair,
+    CactusBigSmall,
+    CactusSmall1,
+    CactusSmall2,
+    CactusSmall3,
+    CactusSmall4,
+    CactusSmall5,
+    CactusSmall6,
     Cloud,
+    DinoDuck1,
+    DinoDuck2,
+    DinoGameOver1,
     DinoGameOver2,
     DinoJump,
-    CactusSmall2,
-    CactusSmall4,
     DinoRun1,
-    DinoDuck1,
-    CactusSmall6,
-    Bird1,
-    CactusBigPair,
     DinoRun2,
-    CactusSmall1,
-    CactusSmall3,
-    CactusBigSmall,
+    Ground,
 }
 
 pub fn obstacle_size(obstacle: &Obstacle) -> (f32, f32) {
     match obstacle {
-        Obstacle::CactusSmall6 => (0.3617021 as f32, 0.7446808 as f32),
-        Obstacle::Cloud => (0.9787234 as f32, 0.28723404 as f32),
-        Obstacle::CactusSmall5 => (0.3617021 as f32, 0.7446808 as f32),
-        Obstacle::DinoJump => (0.9361702 as f32, 1 as f32),
-        Obstacle::CactusSmall4 => (0.3617021 as f32, 0.7446808 as f32),
-        Obstacle::CactusSmall2 => (0.3617021 as f32, 0.7446808 as f32),
-        Obstacle::DinoGameOver2 => (0.9361702 as f32, 1 as f32),
-        Obstacle::Bird2 => (0.9787234 as f32, 0.85106385 as f32),
-        Obstacle::DinoRun2 => (0.9361702 as f32, 1 as f32),
-        Obstacle::CactusSmall3 => (0.3617021 as f32, 0.7446808 as f32),
-        Obstacle::CactusBigSmall => (1.5957447 as f32, 1.0638298 as f32),
-        Obstacle::DinoRun1 => (0.9361702 as f32, 1 as f32),
-        Obstacle::DinoGameOver1 => (0.9361702 as f32, 1 as f32),
-        Obstacle::Ground => (25.531916 as f32, 0.25531915 as f32),
-        Obstacle::DinoDuck1 => (1.2553191 as f32, 0.63829786 as f32),
-        Obstacle::DinoDuck2 => (1.2553191 as f32, 0.63829786 as f32),
-        Obstacle::CactusBig2 => (0.5319149 as f32, 1.0638298 as f32),
-        Obstacle::Bird1 => (0.9787234 as f32, 0.85106385 as f32),
-        Obstacle::CactusBigPair => (1.0425532 as f32, 1.0638298 as f32),
-        Obstacle::CactusSmall1 => (0.3617021 as f32, 0.7446808 as f32),
-        Obstacle::CactusBig1 => (0.5319149 as f32, 1.0638298 as f32),
+        Obstacle::Bird1 => (0.9787234, 0.85106385),
+        Obstacle::Bird2 => (0.9787234, 0.85106385),
+        Obstacle::CactusBig1 => (0.5319149, 1.0638298),
+        Obstacle::CactusBig2 => (0.5319149, 1.0638298),
+        Obstacle::CactusBigPair => (1.0425532, 1.0638298),
+        Obstacle::CactusBigSmall => (1.5957447, 1.0638298),
+        Obstacle::CactusSmall1 => (0.3617021, 0.7446808),
+        Obstacle::CactusSmall2 => (0.3617021, 0.7446808),
+        Obstacle::CactusSmall3 => (0.3617021, 0.7446808),
+        Obstacle::CactusSmall4 => (0.3617021, 0.7446808),
+        Obstacle::CactusSmall5 => (0.3617021, 0.7446808),
+        Obstacle::CactusSmall6 => (0.3617021, 0.7446808),
+        Obstacle::Cloud => (0.9787234, 0.28723404),
+        Obstacle::DinoDuck1 => (1.2553191, 0.63829786),
+        Obstacle::DinoDuck2 => (1.2553191, 0.63829786),
+        Obstacle::DinoGameOver1 => (0.9361702, 1.0),
+        Obstacle::DinoGameOver2 => (0.9361702, 1.0),
+        Obstacle::DinoJump => (0.9361702, 1.0),
+        Obstacle::DinoRun1 => (0.9361702, 1.0),
+        Obstacle::DinoRun2 => (0.9361702, 1.0),
+        Obstacle::Ground => (25.531916, 0.25531915),
     }
 }
 
-pub fn random_cactus(rng: &mut ThreadRng) -> Obstacle {
+pub fn random_cactus<R: Rng>(rng: &mut R) -> Obstacle {
     match rng.gen_range(0..=9) {
-        0 => Obstacle::CactusSmall5,
-        1 => Obstacle::CactusBig1,
-        2 => Obstacle::CactusBig2,
-        3 => Obstacle::CactusSmall2,
-        4 => Obstacle::CactusSmall4,
-        5 => Obstacle::CactusSmall6,
-        6 => Obstacle::CactusBigPair,
-        7 => Obstacle::CactusSmall1,
-        8 => Obstacle::CactusSmall3,
-        9 => Obstacle::CactusBigSmall,
+        0 => Obstacle::CactusBig1,
+        1 => Obstacle::CactusBig2,
+        2 => Obstacle::CactusBigPair,
+        3 => Obstacle::CactusBigSmall,
+        4 => Obstacle::CactusSmall1,
+        5 => Obstacle::CactusSmall2,
+        6 => Obstacle::CactusSmall3,
+        7 => Obstacle::CactusSmall4,
+        8 => Obstacle::CactusSmall5,
+        9 => Obstacle::CactusSmall6,
         _ => unreachable!(),
     }
 }
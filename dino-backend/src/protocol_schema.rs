@@ -0,0 +1,168 @@
+//! JSON Schema and TypeScript definitions of the websocket protocol, generated from the types in
+//! `session_exec`.
+//!
+//! `cargo run -- --emit-schema` and `cargo run -- --emit-typescript` print them, the checked in
+//! copies are `protocol.schema.json` and the frontend's `src/game/protocol.ts`.
+
+use crate::session_exec::{RxData, TxData};
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Map, Value};
+
+/// Schema of every message, `TxData` is what the server sends and `RxData` what it accepts.
+pub fn json_schema() -> Value {
+    let mut gen = SchemaSettings::draft07().into_generator();
+    let tx = gen.subschema_for::<TxData>();
+    let rx = gen.subschema_for::<RxData>();
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Dino multiplayer protocol",
+        "anyOf": [tx, rx],
+        "definitions": gen.take_definitions(),
+    })
+}
+
+pub fn json_schema_string() -> String {
+    serde_json::to_string_pretty(&json_schema()).unwrap() + "\n"
+}
+
+/// The schema's definitions as TypeScript types.
+pub fn typescript() -> String {
+    let schema = json_schema();
+    let mut ts = String::from(
+        "// Generated by `cargo run -- --emit-typescript` in dino-backend, don't edit by hand.\n\
+         // `TxData` is sent by the server, `RxData` by the client.\n",
+    );
+
+    for (name, definition) in schema["definitions"].as_object().unwrap() {
+        ts.push('\n');
+        if let Some(description) = definition["description"].as_str() {
+            ts.push_str(&format!("/** {} */\n", description.replace('\n', " ")));
+        }
+
+        let line = format!("export type {} = {};\n", name, ts_type(definition));
+        let variants: Vec<String> = match (definition["oneOf"].as_array(), &definition["enum"]) {
+            (Some(variants), _) => variants.iter().map(ts_type).collect(),
+            (None, Value::Array(values)) if line.len() > 100 => {
+                values.iter().map(Value::to_string).collect()
+            }
+            _ => {
+                ts.push_str(&line);
+                continue;
+            }
+        };
+
+        ts.push_str(&format!("export type {} =\n", name));
+        for variant in variants {
+            ts.push_str(&format!("    | {}\n", variant));
+        }
+        ts.pop();
+        ts.push_str(";\n");
+    }
+
+    ts
+}
+
+fn ts_type(schema: &Value) -> String {
+    if let Some(reference) = schema["$ref"].as_str() {
+        return reference.trim_start_matches("#/definitions/").to_owned();
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        return union(values.iter().map(|value| value.to_string()));
+    }
+    if let Some(schemas) = schema["anyOf"].as_array().or(schema["oneOf"].as_array()) {
+        return union(schemas.iter().map(ts_type));
+    }
+
+    match &schema["type"] {
+        Value::Array(types) => union(
+            types
+                .iter()
+                .map(|ty| ts_type(&with_type(schema, ty.clone()))),
+        ),
+        Value::String(ty) => match ty.as_str() {
+            "string" => "string".to_owned(),
+            "integer" | "number" => "number".to_owned(),
+            "boolean" => "boolean".to_owned(),
+            "null" => "null".to_owned(),
+            "array" => match &schema["items"] {
+                Value::Array(items) => tuple(items.iter().map(ts_type)),
+                items => {
+                    let item = ts_type(items);
+                    match (schema["minItems"].as_u64(), schema["maxItems"].as_u64()) {
+                        (Some(min), Some(max)) if min == max => {
+                            tuple((0..min).map(|_| item.clone()))
+                        }
+                        _ if item.contains(' ') => format!("Array<{}>", item),
+                        _ => format!("{}[]", item),
+                    }
+                }
+            },
+            "object" => object(schema),
+            _ => "unknown".to_owned(),
+        },
+        _ => "unknown".to_owned(),
+    }
+}
+
+fn object(schema: &Value) -> String {
+    let empty = Map::new();
+    let properties = schema["properties"].as_object().unwrap_or(&empty);
+    let required: Vec<&str> = schema["required"]
+        .as_array()
+        .map(|required| required.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+
+    //the tag first, like they're written in `session_exec`
+    let mut fields: Vec<_> = properties.iter().collect();
+    fields.sort_by_key(|(name, _)| name.as_str() != "type");
+
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|(name, property)| {
+            let optional = if required.contains(&name.as_str()) {
+                ""
+            } else {
+                "?"
+            };
+            format!("{}{}: {}", name, optional, ts_type(property))
+        })
+        .collect();
+
+    if fields.is_empty() {
+        "Record<string, never>".to_owned()
+    } else {
+        format!("{{ {} }}", fields.join("; "))
+    }
+}
+
+fn union(types: impl Iterator<Item = String>) -> String {
+    types.collect::<Vec<_>>().join(" | ")
+}
+
+fn tuple(types: impl Iterator<Item = String>) -> String {
+    format!("[{}]", types.collect::<Vec<_>>().join(", "))
+}
+
+fn with_type(schema: &Value, ty: Value) -> Value {
+    let mut schema = schema.clone();
+    schema["type"] = ty;
+    schema
+}
+
+#[test]
+fn checked_in_definitions_are_up_to_date() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let read =
+        |path: &str| std::fs::read_to_string(format!("{}/{}", root, path)).unwrap_or_default();
+
+    assert!(
+        read("protocol.schema.json") == json_schema_string(),
+        "protocol.schema.json is stale, run `cargo run -- --emit-schema > protocol.schema.json`"
+    );
+    assert!(
+        read("../dino-frontend/src/game/protocol.ts") == typescript(),
+        "protocol.ts is stale, run \
+         `cargo run -- --emit-typescript > ../dino-frontend/src/game/protocol.ts`"
+    );
+}
@@ -0,0 +1,195 @@
+//! Skill ratings: Elo, with every multiplayer result counted as a game between each pair of
+//! players, won by whoever placed higher.
+//!
+//! Ratings aren't stored on their own, they're worked out again from the match store on start.
+//! Only matches played with the server's default rules count, a custom session can make the
+//! game as easy or as short as it likes.
+
+use crate::storage::{MatchRecord, MatchRules, MatchStore};
+use rustc_hash::FxHashMap;
+use schemars::JsonSchema;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Rating of a player before their first rated match.
+pub const INITIAL_RATING: f64 = 1500.0;
+
+/// Most a rating moves in one match, split between the opponents.
+const K: f64 = 32.0;
+
+/// How a single match changed a player's rating.
+#[derive(Serialize, Clone, PartialEq, Debug, JsonSchema)]
+pub struct RatingChange {
+    #[serde(rename = "matchId")]
+    pub match_id: Uuid,
+    #[serde(rename = "endedAt")]
+    pub ended_at: u64,
+    pub rating: f64, //after the match
+    pub change: f64,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug, JsonSchema)]
+pub struct RatedPlayer {
+    pub rank: u32,
+    pub username: String,
+    pub rating: f64,
+    pub games: u32,
+}
+
+struct PlayerRating {
+    rating: f64,
+    history: Vec<RatingChange>, //oldest first
+}
+
+pub struct Ratings {
+    rules: MatchRules, //the rules rated matches are played with
+    players: FxHashMap<String, PlayerRating>,
+}
+
+impl Ratings {
+    pub fn new(rules: MatchRules) -> Self {
+        Self {
+            rules,
+            players: FxHashMap::default(),
+        }
+    }
+
+    /// Rates every match in `store` played with `rules`, oldest first.
+    pub fn from_store(store: &dyn MatchStore, rules: MatchRules) -> Self {
+        let mut ratings = Self::new(rules);
+        for record in store.recent(usize::MAX).iter().rev() {
+            ratings.rate(record);
+        }
+        ratings
+    }
+
+    /// Rates `record` if it was played against other people with the rules ratings are for.
+    /// Bots and players the anti cheat flagged take no part.
+    pub fn rate(&mut self, record: &MatchRecord) {
+        if record.rules != self.rules {
+            return;
+        }
+        let rated: Vec<(&str, u32)> = record
+            .results
+            .iter()
+            .filter(|result| !result.flagged && !record.bots.contains(&result.username))
+            .map(|result| (result.username.as_str(), result.rank))
+            .collect();
+        if rated.len() < 2 {
+            return;
+        }
+
+        let before: Vec<f64> = rated
+            .iter()
+            .map(|(username, _)| self.rating(username).unwrap_or(INITIAL_RATING))
+            .collect();
+        let k = K / (rated.len() - 1) as f64;
+        for (i, (username, rank)) in rated.iter().enumerate() {
+            let change: f64 = rated
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, (_, other_rank))| {
+                    let score = match rank.cmp(other_rank) {
+                        std::cmp::Ordering::Less => 1.0,
+                        std::cmp::Ordering::Equal => 0.5,
+                        std::cmp::Ordering::Greater => 0.0,
+                    };
+                    let expected = 1.0 / (1.0 + 10f64.powf((before[j] - before[i]) / 400.0));
+                    k * (score - expected)
+                })
+                .sum();
+
+            let player = self
+                .players
+                .entry((*username).to_owned())
+                .or_insert_with(|| PlayerRating {
+                    rating: INITIAL_RATING,
+                    history: vec![],
+                });
+            player.rating += change;
+            player.history.push(RatingChange {
+                match_id: record.id,
+                ended_at: record.ended_at,
+                rating: player.rating,
+                change,
+            });
+        }
+    }
+
+    /// `None` until the player's first rated match.
+    pub fn rating(&self, username: &str) -> Option<f64> {
+        self.players.get(username).map(|player| player.rating)
+    }
+
+    /// The player's rating after each of their last `limit` matches, newest first.
+    pub fn history(&self, username: &str, limit: usize) -> Vec<RatingChange> {
+        self.players.get(username).map_or(vec![], |player| {
+            player.history.iter().rev().take(limit).cloned().collect()
+        })
+    }
+
+    /// Highest rated players first.
+    pub fn ladder(&self, limit: usize) -> Vec<RatedPlayer> {
+        let mut players: Vec<(&String, &PlayerRating)> = self.players.iter().collect();
+        players.sort_by(|a, b| b.1.rating.total_cmp(&a.1.rating).then(a.0.cmp(b.0)));
+        players
+            .into_iter()
+            .take(limit)
+            .enumerate()
+            .map(|(i, (username, player))| RatedPlayer {
+                rank: i as u32 + 1,
+                username: username.clone(),
+                rating: player.rating,
+                games: player.history.len() as u32,
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn placing_higher_gains_rating() {
+    use crate::storage::test_record;
+
+    let record = |results: &[(&str, u64, bool)], bots: &[&str]| test_record(0, results, bots);
+    let mut ratings = Ratings::new(record(&[], &[]).rules);
+
+    //nobody to be rated against
+    ratings.rate(&record(&[("a", 90, false), ("Rex", 40, false)], &["Rex"]));
+    ratings.rate(&record(&[("a", 90, false), ("b", 40, true)], &[]));
+    //custom rules aren't rated
+    let mut easy = record(&[("a", 90, false), ("b", 40, false)], &[]);
+    easy.rules.physics.gravity /= 2.0;
+    ratings.rate(&easy);
+    assert_eq!(ratings.rating("a"), None);
+
+    ratings.rate(&record(
+        &[("a", 90, false), ("b", 40, false), ("c", 40, false)],
+        &[],
+    ));
+    let (a, b, c) = (
+        ratings.rating("a").unwrap(),
+        ratings.rating("b").unwrap(),
+        ratings.rating("c").unwrap(),
+    );
+    assert!((a - (INITIAL_RATING + K / 2.0)).abs() < 1e-9);
+    assert_eq!(b, c);
+    assert!(
+        (a + b + c - 3.0 * INITIAL_RATING).abs() < 1e-9,
+        "ratings are zero sum"
+    );
+
+    //beating a higher rated player is worth more than beating an equal one
+    ratings.rate(&record(&[("b", 90, false), ("a", 40, false)], &[]));
+    assert!(ratings.rating("b").unwrap() - b > K / 2.0);
+
+    let ladder: Vec<String> = ratings
+        .ladder(10)
+        .into_iter()
+        .map(|player| player.username)
+        .collect();
+    assert_eq!(ladder, ["b", "a", "c"]);
+    let history = ratings.history("a", 10);
+    assert_eq!(history.len(), 2);
+    assert!(history[0].change < 0.0, "newest first");
+}
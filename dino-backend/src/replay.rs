@@ -0,0 +1,447 @@
+//! Replays: everything needed to watch a finished game again, saved as one JSON file per game.
+//!
+//! The map isn't stored, it's generated again from the seed and the physics.
+
+use crate::map_generator::GameMap;
+use crate::send_msg;
+use crate::session_exec::{GameEvent, PlayerResult, TxData};
+use crate::simulation;
+use crate::storage::{MatchRecord, MatchStore};
+use crate::validator::SessionPhysicsConfig;
+use futures_channel::mpsc::UnboundedSender;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{self, Instant};
+use uuid::Uuid;
+
+/// Bumped whenever a change to the format would break reading older replays.
+pub const VERSION: u32 = 1;
+
+/// Slowest and fastest a replay can be played back at.
+pub const MIN_SPEED: f64 = 0.25;
+pub const MAX_SPEED: f64 = 16.0;
+
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub id: Uuid,
+    pub session_name: String,
+    pub recorded_at: u64, //unix seconds
+    pub seed: u32,
+    pub physics: SessionPhysicsConfig,
+    pub players: Vec<String>, //`Entry::player` is an index into this
+    pub entries: Vec<Entry>,
+    pub results: Vec<PlayerResult>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub t: f64, //milliseconds since the game started
+    #[serde(rename = "p")]
+    pub player: u16,
+    #[serde(flatten)]
+    pub input: Input,
+}
+
+/// What players sent while the game was running, as it was passed on to everyone else.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum Input {
+    Broadcast {
+        pos: [f32; 2],
+    },
+    Event {
+        code: u64,
+        pos: [f64; 2],
+        vel: [f64; 2],
+    },
+    GameEvent {
+        event: GameEvent,
+    },
+    GameOver {
+        score: u64,
+    },
+}
+
+impl Input {
+    /// The message everyone was sent for this input at `t` milliseconds into the game, counting
+    /// position broadcasts with `tick`.
+    fn into_msg(self, username: String, t: f64, tick: &mut u64, ghost: bool) -> TxData {
+        match self {
+            Input::Broadcast { pos } => {
+                *tick += 1;
+                TxData::Broadcast {
+                    username,
+                    pos,
+                    tick: *tick,
+                    ghost,
+                }
+            }
+            Input::Event { code, pos, vel } => TxData::Event {
+                username,
+                code,
+                timestamp: t,
+                pos,
+                vel,
+                ghost,
+            },
+            Input::GameEvent { event } => TxData::GameEvent {
+                username,
+                event,
+                ghost,
+            },
+            Input::GameOver { score } => TxData::UserGameOverBroadcast {
+                username,
+                score,
+                ghost,
+            },
+        }
+    }
+}
+
+impl Replay {
+    pub fn new(session_name: String, seed: u32, physics: SessionPhysicsConfig) -> Self {
+        Self {
+            version: VERSION,
+            id: Uuid::new_v4(),
+            session_name,
+            recorded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            seed,
+            physics,
+            players: vec![],
+            entries: vec![],
+            results: vec![],
+        }
+    }
+
+    pub fn record(&mut self, t: f64, username: &str, input: Input) {
+        let player = match self.players.iter().position(|name| name == username) {
+            Some(player) => player,
+            None => {
+                self.players.push(username.to_owned());
+                self.players.len() - 1
+            }
+        };
+        self.entries.push(Entry {
+            t,
+            player: player as u16,
+            input,
+        });
+    }
+
+    pub fn save(&self, dir: &str) -> io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(path(dir, &self.id), serde_json::to_string(self)?)
+    }
+
+    pub fn load(dir: &str, id: &Uuid) -> io::Result<Self> {
+        let replay: Self = serde_json::from_str(&std::fs::read_to_string(path(dir, id))?)?;
+        if replay.version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("replay version {} isn't supported", replay.version),
+            ));
+        }
+        Ok(replay)
+    }
+
+    /// Sends the game to `tx` the way it was sent to the players, `speed` times as fast. Stops
+    /// early if the connection closes.
+    pub async fn play(self, tx: UnboundedSender<TxData>, speed: f64) {
+        let physics = self.physics;
+        let farthest = self
+            .results
+            .iter()
+            .map(|result| result.distance)
+            .fold(0.0, f64::max);
+        let mut map = GameMap::new(
+            physics.initial_vel,
+            physics.x_acc,
+            physics.gravity,
+            physics.jump_vel,
+            self.seed,
+        );
+        let map = map
+            .obstacles_until(farthest + simulation::DINO_X_OFFSET + 2.0)
+            .to_vec();
+
+        send_msg!(
+            tx,
+            TxData::GameCountdownStart {
+                duration: 1,
+                physics
+            }
+        );
+        send_msg!(tx, TxData::Map { map });
+        send_msg!(tx, TxData::GameStart);
+
+        let start = Instant::now();
+        let mut ticks = vec![0; self.players.len()];
+        for entry in self.entries {
+            time::sleep_until(start + Duration::from_secs_f64(entry.t * 0.001 / speed)).await;
+            if tx.is_closed() {
+                return;
+            }
+
+            let username = self.players[entry.player as usize].clone();
+            let tick = &mut ticks[entry.player as usize];
+            send_msg!(tx, entry.input.into_msg(username, entry.t, tick, false));
+        }
+
+        send_msg!(
+            tx,
+            TxData::GameResults {
+                results: self.results,
+                replay: Some(self.id),
+            }
+        );
+    }
+}
+
+/// One player's run out of a replay, played alongside a new game on the same map.
+pub struct Ghost {
+    pub username: String,
+    pub score: u64,
+    entries: Vec<(f64, Input)>,
+    next: usize,
+    tick: u64,
+}
+
+impl Ghost {
+    /// Messages for everything the ghost did up to `t` milliseconds into the game.
+    pub fn due(&mut self, t: f64) -> Vec<TxData> {
+        let mut msgs = vec![];
+        while let Some((at, input)) = self.entries.get(self.next) {
+            if *at > t {
+                break;
+            }
+            let msg = input
+                .clone()
+                .into_msg(self.username.clone(), *at, &mut self.tick, true);
+            msgs.push(msg);
+            self.next += 1;
+        }
+        msgs
+    }
+
+    /// Back to the start, for another game on the same map.
+    pub fn reset(&mut self) {
+        self.next = 0;
+        self.tick = 0;
+    }
+}
+
+/// The best run of every player on every map that has a replay, so finding a ghost only reads
+/// the one replay it's in. Built from the match store on start like the ratings.
+#[derive(Default)]
+pub struct GhostIndex {
+    maps: FxHashMap<MapKey, FxHashMap<String, (Uuid, u64)>>, //value: replay and score by username
+}
+
+type MapKey = (u32, [u32; 4]); //seed and the bits of the physics
+
+impl GhostIndex {
+    pub fn from_store(store: &dyn MatchStore) -> Self {
+        let mut index = Self::default();
+        for record in store.recent(usize::MAX).iter().rev() {
+            index.add(record);
+        }
+        index
+    }
+
+    /// Adds the runs of `record` if it has a replay. Bots and runs the anti cheat flagged don't
+    /// count, ties go to whoever got there first.
+    pub fn add(&mut self, record: &MatchRecord) {
+        let Some(replay) = record.replay else {
+            return;
+        };
+        let runs = self
+            .maps
+            .entry(map_key(record.seed, &record.rules.physics))
+            .or_default();
+        for result in &record.results {
+            if result.flagged || record.bots.contains(&result.username) {
+                continue;
+            }
+            match runs.get(&result.username) {
+                Some((_, best)) if *best >= result.score => (),
+                _ => {
+                    runs.insert(result.username.clone(), (replay, result.score));
+                }
+            }
+        }
+    }
+
+    /// The replay with the best run on the map of `seed` and `physics` and whose run it is,
+    /// only `username`'s runs if it's set.
+    pub fn best(
+        &self,
+        seed: u32,
+        physics: &SessionPhysicsConfig,
+        username: Option<&str>,
+    ) -> Option<(Uuid, &str)> {
+        let runs = self.maps.get(&map_key(seed, physics))?;
+        let (username, (replay, _)) = match username {
+            Some(username) => runs.get_key_value(username)?,
+            //ties go to the earlier replay, uuids aren't ordered by time so it's by username
+            None => runs
+                .iter()
+                .max_by(|a, b| (a.1).1.cmp(&(b.1).1).then(b.0.cmp(a.0)))?,
+        };
+        Some((*replay, username))
+    }
+}
+
+fn map_key(seed: u32, physics: &SessionPhysicsConfig) -> MapKey {
+    let physics = [
+        physics.initial_vel,
+        physics.x_acc,
+        physics.gravity,
+        physics.jump_vel,
+    ];
+    (seed, physics.map(f32::to_bits))
+}
+
+/// `username`'s run out of the replay `id` saved in `dir`.
+pub fn load_ghost(dir: &str, id: &Uuid, username: &str) -> io::Result<Ghost> {
+    let replay = Replay::load(dir, id)?;
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, "no such player in the replay");
+    let player = replay
+        .players
+        .iter()
+        .position(|p| p == username)
+        .ok_or_else(not_found)?;
+    let score = replay
+        .results
+        .iter()
+        .find(|result| result.username == username)
+        .ok_or_else(not_found)?
+        .score;
+    let entries = replay
+        .entries
+        .into_iter()
+        .filter(|entry| entry.player as usize == player)
+        .map(|entry| (entry.t, entry.input))
+        .collect();
+    Ok(Ghost {
+        username: username.to_owned(),
+        score,
+        entries,
+        next: 0,
+        tick: 0,
+    })
+}
+
+fn path(dir: &str, id: &Uuid) -> PathBuf {
+    Path::new(dir).join(format!("{}.json", id))
+}
+
+#[test]
+fn replays_are_played_back_in_order() {
+    use futures_util::StreamExt;
+
+    let mut replay = Replay::new("lunch".to_owned(), 1, SessionPhysicsConfig::default());
+    replay.record(10.0, "a", Input::Broadcast { pos: [1.0, 0.0] });
+    replay.record(
+        20.0,
+        "b",
+        Input::GameEvent {
+            event: GameEvent::Jump { pos: 2.0 },
+        },
+    );
+    replay.record(30.0, "a", Input::GameOver { score: 3 });
+
+    let dir = std::env::temp_dir().join(format!("dino-replays-{}", replay.id));
+    let dir = dir.to_str().unwrap();
+    replay.save(dir).unwrap();
+    let replay = Replay::load(dir, &replay.id).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    assert_eq!(replay.players, ["a", "b"]);
+
+    let (tx, rx) = futures_channel::mpsc::unbounded();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    runtime.block_on(replay.play(tx, MAX_SPEED));
+    let sent: Vec<TxData> = runtime.block_on(rx.collect());
+
+    let types: Vec<String> = sent
+        .iter()
+        .map(|msg| {
+            serde_json::to_value(msg).unwrap()["type"]
+                .as_str()
+                .unwrap()
+                .to_owned()
+        })
+        .collect();
+    assert_eq!(
+        types,
+        [
+            "GameCountdownStart",
+            "Map",
+            "GameStart",
+            "Broadcast",
+            "GameEvent",
+            "UserGameOverBroadcast",
+            "GameResults",
+        ]
+    );
+}
+
+#[test]
+fn ghosts_are_the_best_unflagged_human_run_on_the_map() {
+    let physics = SessionPhysicsConfig::default();
+    let dir = std::env::temp_dir().join(format!("dino-ghosts-{}", Uuid::new_v4()));
+    let dir = dir.to_str().unwrap();
+    let mut index = GhostIndex::default();
+    let mut save = |seed: u32, results: &[(&str, u64, bool)], bots: &[&str]| {
+        let mut record = crate::storage::test_record(0, results, bots);
+        let mut replay = Replay::new("lunch".to_owned(), seed, physics);
+        for (i, result) in record.results.iter().enumerate() {
+            let t = i as f64 * 10.0;
+            replay.record(t, &result.username, Input::Broadcast { pos: [1.0, 0.0] });
+            let score = result.score;
+            replay.record(t + 1.0, &result.username, Input::GameOver { score });
+        }
+        replay.results = record.results.clone();
+        replay.save(dir).unwrap();
+        record.seed = seed;
+        record.replay = Some(replay.id);
+        index.add(&record);
+    };
+    save(1, &[("a", 100, false), ("b", 50, false)], &[]);
+    save(1, &[("b", 80, true), ("Rex", 900, false)], &["Rex"]);
+    save(2, &[("a", 500, false)], &[]);
+
+    let load = |(id, username): (Uuid, &str)| load_ghost(dir, &id, username).unwrap();
+    let best = index.best(1, &physics, None).map(load).unwrap();
+    let personal_best = index.best(1, &physics, Some("b")).map(load).unwrap();
+    let missing = index.best(3, &physics, None);
+    std::fs::remove_dir_all(dir).unwrap();
+
+    assert_eq!((best.username.as_str(), best.score), ("a", 100));
+    assert_eq!(personal_best.score, 50);
+    assert!(missing.is_none());
+
+    let mut ghost = personal_best;
+    assert!(ghost.due(5.0).is_empty());
+    let msgs = ghost.due(20.0);
+    assert!(matches!(
+        msgs.as_slice(),
+        [
+            TxData::Broadcast { ghost: true, .. },
+            TxData::UserGameOverBroadcast {
+                score: 50,
+                ghost: true,
+                ..
+            }
+        ]
+    ));
+}
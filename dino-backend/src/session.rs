@@ -1,31 +1,49 @@
-#![allow(unused)]
-
+use crate::auth::{PasswordHash, TokenSigner};
+use crate::bot::{self, Bot, BotLevel};
 use crate::config_options::SessionConfig;
 
 use crate::map_generator::GameMap;
+use crate::replay::{Ghost, Input, Replay};
 use crate::session_exec::{
-    GameEvent, QueryResponseType, QueryType, RxData, TransmissionQueue, TxData,
+    ErrorCode, GameEvent, HostCommand, LeaderboardEntry, PlayerResult, PlayerSnapshot, PlayerState,
+    QueryResponseType, QueryType, RxData, SessionVisibility, TxData,
+};
+use crate::simulation::{self, DinoSim};
+use crate::storage::{MatchRecord, MatchRules};
+use crate::validator::{
+    AntiCheat, AntiCheatAction, AntiCheatConfig, PlayerEvent, SessionPhysicsConfig, Violation,
 };
 
 use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio_tungstenite::tungstenite::protocol::Message;
-
-use serde::{Deserialize, Serialize};
+use futures_util::StreamExt;
 
 use uuid::Uuid;
 
-use rustc_hash::FxHashMap;
-use std::cell::Cell;
-use std::net::SocketAddr;
-use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::task::{Context, Poll};
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// Longest a host can make their lobby wait with `HostCommand::SetWaitTime`.
+const MAX_WAIT_TIME: u64 = 5 * 60;
+
+/// How often dinos are checked for collisions while the game is running.
+const COLLISION_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often everyone is sent the leaderboard while the game is running.
+const LEADERBOARD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often bots move and broadcast their position.
+const BOT_INTERVAL: Duration = Duration::from_millis(50);
 
-use std::time::{self, Duration, Instant, SystemTime};
+/// How often what ghosts did is sent out, they move the way their recording did in between.
+const GHOST_INTERVAL: Duration = Duration::from_millis(20);
 
-pub const X_ACC: f64 = 0.3;
-pub const INITIAL_X_VEL: f64 = 8.0;
-pub const GRAVITY: f64 = -60.0;
-pub const JUMP_VEL: f64 = 15.0;
+/// How far off a player's reports may be before they count as a violation.
+const POSITION_TOLERANCE: f64 = 3.0;
+const POSITION_TOLERANCE_RATIO: f64 = 0.05;
+const LATENCY_TOLERANCE: u128 = 2000; //ms
 
 #[derive(PartialEq)]
 pub enum SessionStatus {
@@ -49,16 +67,11 @@ pub enum SessionStatus {
 #[macro_export]
 macro_rules! send_msg {
     ($channel:expr, $msg:expr) => {{
-        $channel.unbounded_send($msg)
+        //the receiving end only goes away once the connection is closed, nothing to do then
+        let _ = $channel.unbounded_send($msg);
     }};
 }
 
-pub enum MessageParseResult {
-    ToTextError,
-    SerdeError(serde_json::Error),
-    Parsed(RxData),
-}
-
 #[macro_export]
 macro_rules! parse_msg {
     ($message:expr) => {
@@ -66,6 +79,10 @@ macro_rules! parse_msg {
     };
 }
 
+/// Maximum number of messages read from a single player channel per poll, so one chatty
+/// client can't starve the rest of the session.
+pub const MAX_READ_COUNT: usize = 128;
+
 pub struct PlayerChannel {
     pub tx: UnboundedSender<TxData>,
     pub rx: UnboundedReceiver<RxData>,
@@ -78,43 +95,120 @@ pub struct Session {
     host_id: Uuid,
     player_data: FxHashMap<Uuid, PlayerData>,
     game_data: GameData,
+    physics: SessionPhysicsConfig,
+    anti_cheat: AntiCheat,
+    tokens: TokenSigner,
     status: SessionStatus,
-    timers: FxHashMap<Uuid, Rc<(SystemTime, fn(&mut Self), bool)>>,
+    timers: FxHashMap<Uuid, SessionTimer>,
     has_finished: bool,
     config: SessionConfig,
     addr_map: FxHashMap<SocketAddr, Uuid>,
     // channels: Rc<FxHashMap<Uuid, Rc<PlayerChannel>>>,
-    receivers: Cell<FxHashMap<Uuid, UnboundedReceiver<RxData>>>,
+    receivers: FxHashMap<Uuid, UnboundedReceiver<RxData>>,
     senders: FxHashMap<Uuid, UnboundedSender<TxData>>,
+    inbox: Vec<(Uuid, RxData)>, //messages received by `poll_recv`, processed in `game_loop`
+    dropped: Vec<Uuid>,         //players whose connection `poll_recv` found closed
+    spectators: FxHashMap<Uuid, PlayerChannel>, //get everything that's emitted, never players
+    visibility: SessionVisibility,
+    join_code: String,
+    password: Option<PasswordHash>,
+    locked: bool, //no new players while set
+    banned_names: FxHashSet<String>,
+    banned_ips: FxHashSet<IpAddr>,
+    wait_time: Duration, //how long the lobby waits for players, again after a rematch
+    rematch_votes: FxHashSet<Uuid>,
+    rematch_same_map: bool, //the host's choice
+    record_replays: bool,
+    replay: Option<Replay>,             //of the running game
+    finished_replays: Vec<Replay>,      //not saved yet
+    finished_matches: Vec<MatchRecord>, //not stored yet
+    bots: FxHashMap<Uuid, Bot>,         //players the session controls, they have no channels
+    ghosts: Vec<Ghost>,                 //recorded runs on the same map, they aren't players
+}
+
+struct SessionTimer {
+    due: Instant,
+    f: fn(&mut Session),
+    interval: Option<Duration>,
 }
 
 impl Session {
-    pub fn new(session_name: String, config: SessionConfig) -> Self {
+    pub fn new(
+        session_name: String,
+        config: SessionConfig,
+        seed: u32,
+        tokens: TokenSigner,
+    ) -> Self {
+        let physics = config.physics;
         Self {
             session_id: Uuid::new_v4(),
             session_name,
             host_id: Uuid::nil(),
             player_data: FxHashMap::default(),
-            game_data: GameData {
-                map: GameMap::new(
-                    INITIAL_X_VEL as f32,
-                    X_ACC as f32,
-                    GRAVITY as f32,
-                    JUMP_VEL as f32,
-                ),
-                sync_score: 0,
-            },
+            game_data: GameData::new(physics, seed),
+            physics,
+            anti_cheat: new_anti_cheat(physics),
+            tokens,
             status: SessionStatus::Uninit,
             timers: FxHashMap::default(),
             has_finished: false,
             config,
             addr_map: FxHashMap::default(),
             // channels: Rc::new(FxHashMap::default()),
-            receivers: Cell::new(FxHashMap::default()),
+            receivers: FxHashMap::default(),
             senders: FxHashMap::default(),
+            inbox: vec![],
+            dropped: vec![],
+            spectators: FxHashMap::default(),
+            visibility: SessionVisibility::Public,
+            join_code: String::new(),
+            password: None,
+            locked: false,
+            banned_names: FxHashSet::default(),
+            banned_ips: FxHashSet::default(),
+            wait_time: Duration::from_secs(MAX_WAIT_TIME),
+            rematch_votes: FxHashSet::default(),
+            rematch_same_map: false,
+            record_replays: false,
+            replay: None,
+            finished_replays: vec![],
+            finished_matches: vec![],
+            bots: FxHashMap::default(),
+            ghosts: vec![],
         }
     }
 
+    /// For quick play, whose sessions skip the lobby: they launch as soon as they're made and
+    /// again as soon as a rematch is agreed on.
+    pub fn without_lobby(mut self) -> Self {
+        self.wait_time = Duration::ZERO;
+        self
+    }
+
+    pub fn with_replays(mut self, record: bool) -> Self {
+        self.record_replays = record;
+        self
+    }
+
+    /// Recorded runs played alongside every game, they never collide or score.
+    pub fn with_ghosts(mut self, ghosts: Vec<Ghost>) -> Self {
+        self.ghosts = ghosts;
+        self
+    }
+
+    /// Sets who can find and join the session, an empty `password` is the same as none.
+    pub fn with_access(
+        mut self,
+        visibility: SessionVisibility,
+        join_code: String,
+        password: Option<&str>,
+    ) -> Self {
+        self.visibility = visibility;
+        self.join_code = join_code;
+        self.password = password.filter(|p| !p.is_empty()).map(PasswordHash::new);
+        self
+    }
+
     /// Returns `Err` if the username is invalidated
     pub fn with_host(
         mut self,
@@ -130,14 +224,20 @@ impl Session {
         let host_id = Uuid::new_v4();
         self.host_id = host_id;
 
-        self.player_data
-            .insert(host_id, PlayerData::new(host_id, username, addr));
+        let mut host = PlayerData::new(host_id, username, addr);
+        //`channel` is dropped below, the host counts as gone until they log in
+        host.disconnect();
+        self.player_data.insert(host_id, host);
+        let _ = self.anti_cheat.register_player(host_id);
+        let token = self.issue_token(&host_id);
 
         send_msg!(
             channel.tx,
             TxData::SessionCreationResponse {
                 creation_succeeded: true,
                 session_id: Some(self.session_id),
+                seed: Some(self.seed()),
+                join_code: Some(self.join_code.clone()),
             }
         );
 
@@ -146,22 +246,17 @@ impl Session {
             TxData::UserCreationResponse {
                 creation_succeeded: true,
                 user_id: Some(host_id),
+                token,
             }
         );
 
-        //5 minutes max wait time
-        let id = self.set_timeout(
-            |s| {
-                s.launch_game();
-                let _ = s.game_data.map.get_map(0, 99);
-            },
-            Duration::from_secs(wait_time), //TODO: change wait time back to 5 minutes
+        //TODO: cap the wait time at 5 minutes
+        self.wait_time = Duration::from_secs(wait_time);
+        self.wait_for_players(self.wait_time);
+        self.set_timeout(
+            Self::migrate_host,
+            Duration::from_secs(self.config.reconnect_grace),
         );
-        self.status = SessionStatus::Waiting {
-            start_time: SystemTime::now(),
-            timeout: id,
-            duration: Duration::from_secs(wait_time),
-        };
 
         Ok(self)
     }
@@ -178,90 +273,186 @@ impl Session {
         &self.status
     }
 
+    pub fn visibility(&self) -> SessionVisibility {
+        self.visibility
+    }
+
+    pub fn join_code(&self) -> &str {
+        &self.join_code
+    }
+
+    fn password_matches(&self, password: Option<&str>) -> bool {
+        match self.password {
+            Some(hash) => password.is_some_and(|p| hash.matches(p)),
+            None => true,
+        }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.game_data.map.seed()
+    }
+
     fn set_timeout(&mut self, f: fn(&mut Self), duration: Duration) -> Uuid {
         let id = Uuid::new_v4();
-        self.timers
-            .insert(id, Rc::new((SystemTime::now() + duration, f, false)));
+        self.timers.insert(
+            id,
+            SessionTimer {
+                due: Instant::now() + duration,
+                f,
+                interval: None,
+            },
+        );
         id
     }
 
     fn set_interval(&mut self, f: fn(&mut Self), duration: Duration) -> Uuid {
         let id = Uuid::new_v4();
-        self.timers
-            .insert(id, Rc::new((SystemTime::now() + duration, f, true)));
+        self.timers.insert(
+            id,
+            SessionTimer {
+                due: Instant::now() + duration,
+                f,
+                interval: Some(duration),
+            },
+        );
         id
     }
 
     fn exec_timers(&mut self) {
+        let now = Instant::now();
         let mut exec_list = vec![];
         let mut remove_list = vec![];
-        for (id, rc) in &self.timers {
-            if SystemTime::now() > rc.0 {
-                // rc.1(self, tx);
-                exec_list.push(rc.1);
-                if !rc.2 {
-                    remove_list.push(id.clone());
+        for (id, timer) in &mut self.timers {
+            if now >= timer.due {
+                exec_list.push(timer.f);
+                match timer.interval {
+                    Some(interval) => timer.due = now + interval,
+                    None => remove_list.push(*id),
                 }
             }
         }
 
-        for f in exec_list {
-            f(self);
-        }
         for id in remove_list {
             self.timers.remove(&id);
         }
+        for f in exec_list {
+            f(self);
+        }
     }
 
-    fn broadcast(&mut self, id: &Uuid, data: TxData) {
-        self.senders.values().for_each(|sender| {
-            send_msg!(sender, data.clone());
-        });
+    /// The earliest point in time at which `game_loop` has something to do without any new
+    /// messages arriving: a timer firing or the game running out of time.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let game_end = match self.status {
+            SessionStatus::Active {
+                start_time,
+                max_duration,
+            } => Some(start_time + max_duration),
+            _ => None,
+        };
+
+        self.timers
+            .values()
+            .map(|timer| timer.due)
+            .chain(game_end)
+            .min()
     }
 
     fn emit(&mut self, data: TxData) {
-        self.senders.values().for_each(|sender| {
-            send_msg!(sender, data.clone());
-        });
+        self.senders
+            .values()
+            .chain(self.spectators.values().map(|spectator| &spectator.tx))
+            .for_each(|sender| {
+                send_msg!(sender, data.clone());
+            });
     }
 
-    pub fn host_addr(&self) -> &SocketAddr {
-        &self.player_data.get(&self.host_id).unwrap().addr
+    /// The channel to a player or spectator.
+    fn sender(&self, id: &Uuid) -> Option<&UnboundedSender<TxData>> {
+        self.senders
+            .get(id)
+            .or_else(|| self.spectators.get(id).map(|spectator| &spectator.tx))
     }
 
-    pub fn on_game_event(
-        &mut self,
-        tx: &mut TransmissionQueue,
-        addr: SocketAddr,
-        id: &Uuid,
-        event: GameEvent,
-    ) {
-        let username = if let Some(player) = self.player_data.get_mut(&id) {
+    pub fn on_game_event(&mut self, id: &Uuid, event: GameEvent) {
+        if !matches!(self.status, SessionStatus::Active { .. }) {
+            return;
+        }
+
+        let physics = self.physics;
+        //game events don't carry a timestamp, they're checked against the time they arrived
+        let t = self.game_elapsed_time().unwrap_or(0.0) as u128;
+        let reported = match event {
+            GameEvent::Jump { pos } => PlayerEvent::Jump { x: pos as f64, t },
+            GameEvent::DuckStart { pos } | GameEvent::DuckEnd { pos } => {
+                PlayerEvent::Duck { x: pos as f64, t }
+            }
+        };
+        self.validate_event(id, reported);
+
+        let username = if let Some(player) = self.player_data.get_mut(id) {
+            //events are placed by position, the time it was reached is the same for everyone
+            match event {
+                GameEvent::Jump { pos } => {
+                    player
+                        .dino
+                        .jump(simulation::time_at_x(pos as f64, &physics), &physics);
+                }
+                GameEvent::DuckStart { pos } => player
+                    .dino
+                    .duck_start(simulation::time_at_x(pos as f64, &physics)),
+                GameEvent::DuckEnd { pos } => player
+                    .dino
+                    .duck_end(simulation::time_at_x(pos as f64, &physics)),
+            }
             player.username.clone()
         } else {
             return;
         };
 
-        self.broadcast(id, TxData::GameEvent { username, event });
+        self.record(
+            id,
+            Input::GameEvent {
+                event: event.clone(),
+            },
+        );
+        self.emit(TxData::GameEvent {
+            username,
+            event,
+            ghost: false,
+        });
     }
 
     #[inline(always)]
-    pub fn on_broadcast_req(&mut self, id: &Uuid, pos_y: f32, pos_x: f32, tick: u64) {
-        let (username, tick) = if let Some(player) = self.player_data.get_mut(&id) {
+    pub fn on_broadcast_req(&mut self, id: &Uuid, pos_y: f32, pos_x: f32) {
+        let (username, tick) = if let Some(player) = self.player_data.get_mut(id) {
             player.curr_tick += 1;
+            player.last_pos = [pos_x, pos_y];
             (player.username.clone(), player.curr_tick)
         } else {
             return;
         };
 
-        self.broadcast(
+        self.validate_event(
+            id,
+            PlayerEvent::PositionBroadcast {
+                x: pos_x as f64,
+                y: pos_y,
+            },
+        );
+        self.record(
             id,
-            TxData::Broadcast {
-                username,
+            Input::Broadcast {
                 pos: [pos_x, pos_y],
-                tick,
             },
-        )
+        );
+
+        self.emit(TxData::Broadcast {
+            username,
+            pos: [pos_x, pos_y],
+            tick,
+            ghost: false,
+        })
     }
 
     #[inline(always)]
@@ -283,32 +474,63 @@ impl Session {
             return;
         } //the client maybe messing with us ;)
 
+        match code {
+            0 => {
+                self.validate_event(
+                    player_id,
+                    PlayerEvent::PositionBroadcast {
+                        x: pos[0],
+                        y: pos[1] as f32,
+                    },
+                );
+                if let Some(player) = self.player_data.get_mut(player_id) {
+                    player.last_pos = [pos[0] as f32, pos[1] as f32];
+                }
+            }
+            1 => {
+                self.validate_event(
+                    player_id,
+                    PlayerEvent::Jump {
+                        x: pos[0],
+                        t: timestamp.max(0.0) as u128,
+                    },
+                );
+                let physics = self.physics;
+                if let Some(player) = self.player_data.get_mut(player_id) {
+                    player.dino.jump(timestamp.min(t_now) * 0.001, &physics);
+                }
+            }
+            _ => (),
+        }
+
         let mut latency = t_now - timestamp;
-        if latency < 0.0 { latency = 0.0 }
-        let dt = latency as f64 * 0.001;
-        
+        if latency < 0.0 {
+            latency = 0.0
+        }
+        let dt = latency * 0.001;
+        let (x_acc, gravity) = (self.physics.x_acc as f64, self.physics.gravity as f64);
 
         let mut new_vel = None;
         let mut new_pos = None;
         match code {
             0 => {
                 //position update
-                new_vel = Some([vel[0] + X_ACC * dt, vel[1]]);
-                new_pos = Some([pos[0] + vel[0] * dt + 0.5 * X_ACC * dt * dt, pos[1]]);
+                new_vel = Some([vel[0] + x_acc * dt, vel[1]]);
+                new_pos = Some([pos[0] + vel[0] * dt + 0.5 * x_acc * dt * dt, pos[1]]);
             }
             1 => {
                 //jump
                 new_pos = Some([
-                    pos[0] + vel[0] * dt + 0.5 * X_ACC * dt * dt,
-                    pos[1] + f64::max(vel[1] * dt + 0.5 * dt * dt * GRAVITY, 0.0),
+                    pos[0] + vel[0] * dt + 0.5 * x_acc * dt * dt,
+                    pos[1] + f64::max(vel[1] * dt + 0.5 * dt * dt * gravity, 0.0),
                 ]);
 
                 new_vel = Some([
-                    vel[0] + X_ACC * dt,
+                    vel[0] + x_acc * dt,
                     if new_pos.unwrap()[1] <= 0.0 {
                         0.0
                     } else {
-                        vel[1] + GRAVITY * dt
+                        vel[1] + gravity * dt
                     },
                 ]);
             }
@@ -316,12 +538,21 @@ impl Session {
         }
 
         if let (Some(new_pos), Some(new_vel)) = (new_pos, new_vel) {
+            self.record(
+                player_id,
+                Input::Event {
+                    code,
+                    pos: new_pos,
+                    vel: new_vel,
+                },
+            );
             self.emit(TxData::Event {
                 username: self.player_data.get(player_id).unwrap().username.clone(),
                 code,
                 timestamp: t_now,
                 pos: new_pos,
                 vel: new_vel,
+                ghost: false,
             })
         }
     }
@@ -329,63 +560,361 @@ impl Session {
     #[inline(always)]
     pub fn on_recv(&mut self, player_id: &Uuid, rx_data: RxData) {
         match rx_data {
-            RxData::BroadcastReq { pos: [pos_x, pos_y], tick } => {
-                self.on_broadcast_req(player_id, pos_y, pos_x, tick)
+            //the tick is counted here, a client's own count can't be trusted
+            RxData::BroadcastReq { pos: [pos_x, pos_y], .. } => {
+                self.on_broadcast_req(player_id, pos_y, pos_x)
             }
             RxData::Event {
-                timestamp, code, pos, vel,  
+                timestamp, code, pos, vel,
             } => {
                 self.on_event(player_id, timestamp, code, pos, vel);
             },
-            RxData::LaunchGame { user_id, .. } => self.launch_game_req(&user_id),
-            RxData::Map { user_id, index, .. } => self.map_req(&user_id, index),
-            // RxData::GameOver { user_id, .. } => self.user_game_over(tx, user_id, addr),
+            RxData::GameEvent { event, .. } => self.on_game_event(player_id, event),
+            RxData::ValidationData {
+                pos_x,
+                score,
+                timestamp,
+                ..
+            } => self.validate_event(
+                player_id,
+                PlayerEvent::ScoreClaim {
+                    x: pos_x,
+                    score,
+                    t: timestamp as u128,
+                },
+            ),
+            RxData::GameOver { token, .. } => {
+                if self.authenticate(&token) == Some(*player_id) {
+                    self.client_game_over(player_id)
+                } else {
+                    self.send_error(player_id, ErrorCode::Unauthorized, "GameOver")
+                }
+            }
+            RxData::LaunchGame { token, .. } => {
+                if self.authenticate(&token) == Some(*player_id) {
+                    self.launch_game_req(player_id)
+                } else {
+                    self.send_error(player_id, ErrorCode::Unauthorized, "LaunchGame")
+                }
+            }
+            RxData::Rematch {
+                token, same_map, ..
+            } => {
+                if self.authenticate(&token) == Some(*player_id) {
+                    self.rematch_vote(player_id, same_map)
+                } else {
+                    self.send_error(player_id, ErrorCode::Unauthorized, "Rematch")
+                }
+            }
+            RxData::HostCommand { token, command, .. } => {
+                if self.authenticate(&token) == Some(*player_id) && *player_id == self.host_id {
+                    self.host_command(player_id, command)
+                } else {
+                    self.send_error(player_id, ErrorCode::Unauthorized, "HostCommand")
+                }
+            }
+            RxData::Map { index, .. } => self.map_req(player_id, index),
             RxData::Query { query: QueryType::SessionStatus { session_id } } => {
                 if session_id == self.session_id {
                     let (status, time) = self.get_status();
-                    
-                    send_msg!(self.senders.get_mut(player_id).unwrap(), TxData::QueryResponse { query_res: QueryResponseType::SessionStatus { status , time }});
+
+                    let seed = self.seed();
+                    send_msg!(self.sender(player_id).unwrap(), TxData::QueryResponse { query_res: QueryResponseType::SessionStatus { status , time, seed }});
                 }
             }
+            RxData::CreateSession { .. }
+            | RxData::DailyChallenge { .. }
+            | RxData::CreateUser { .. }
+            | RxData::JoinWithCode { .. }
+            | RxData::Login { .. }
+            | RxData::Spectate { .. }
+            | RxData::QuickPlay { .. } => {
+                self.send_error(player_id, ErrorCode::AlreadyInSession, rx_data.name())
+            }
             _ => println!(
                 "[session] WARNING: Every other conditions should be already handled in `SessionExecutor`"
             ),
         }
     }
 
-    pub fn user_game_over(
-        &mut self,
-        tx: &mut TransmissionQueue,
-        user_id: &Uuid,
-        addr: SocketAddr,
-    ) -> Result<usize, ()> {
-        let score = if let SessionStatus::Active { start_time, .. } = self.status {
-            self.curr_score(start_time)
-        } else {
-            return Err(());
-        };
+    /// Spectators are only ever sent what everyone gets, except for the map and the session's
+    /// status.
+    fn on_spectator_recv(&mut self, id: &Uuid, rx_data: RxData) {
+        match rx_data {
+            RxData::Map { index, .. } => self.map_req(id, index),
+            RxData::Query {
+                query: QueryType::SessionStatus { .. },
+            } => self.on_recv(id, rx_data),
+            _ => self.send_error(id, ErrorCode::Unauthorized, rx_data.name()),
+        }
+    }
 
-        let username = if let Some(player) = self.player_data.get_mut(&user_id) {
-            if addr != player.addr {
-                return Err(());
+    fn send_error(&self, id: &Uuid, code: ErrorCode, request: &str) {
+        if let Some(sender) = self.sender(id) {
+            send_msg!(sender, TxData::error(code, request));
+        }
+    }
+
+    /// The client noticed a crash before the server did, there's no point in making them wait.
+    /// They're scored where the server last saw their dino alive, reporting a crash can't beat
+    /// the score the server would have given them.
+    fn client_game_over(&mut self, user_id: &Uuid) {
+        if !matches!(self.status, SessionStatus::Active { .. }) {
+            return;
+        }
+        let score = match self.player_data.get(user_id) {
+            Some(player) if player.score == 0 => {
+                let x = simulation::x_at(player.dino.checked_until(), &self.physics);
+                x.round().max(1.0) as u64
             }
+            _ => return,
+        };
+        self.player_game_over(user_id, score);
+    }
+
+    fn player_game_over(&mut self, user_id: &Uuid, score: u64) {
+        let username = if let Some(player) = self.player_data.get_mut(user_id) {
             player.score = score;
             player.username.clone()
         } else {
-            return Err(());
+            return;
         };
 
-        send_msg!(
-            self.senders.get(user_id).unwrap(),
-            TxData::UserGameOver {
-                score,
-                user_id: *user_id
+        //anything the player sends after this is suspicious
+        if let Some(elapsed) = self.game_elapsed_time() {
+            let x = score as f64;
+            let t = (simulation::time_at_x(x, &self.physics) * 1000.0) as u128;
+            let _ = self.anti_cheat.register_player_event(
+                *user_id,
+                PlayerEvent::GameOverBroadcast { x, t },
+                elapsed as u128,
+            );
+        }
+
+        self.record(user_id, Input::GameOver { score });
+        if let Some(sender) = self.senders.get(user_id) {
+            send_msg!(
+                sender,
+                TxData::UserGameOver {
+                    score,
+                    user_id: *user_id
+                }
+            );
+        }
+        self.emit(TxData::UserGameOverBroadcast {
+            username,
+            score,
+            ghost: false,
+        });
+    }
+
+    /// Replays every living dino's inputs against the map up to a little while ago and ends
+    /// the game of whoever ran into an obstacle.
+    fn check_collisions(&mut self) {
+        let elapsed = if let Some(elapsed) = self.game_elapsed_time() {
+            elapsed * 0.001
+        } else {
+            return;
+        };
+        let until = elapsed - simulation::INPUT_DELAY;
+        if until <= 0.0 {
+            return;
+        }
+
+        let physics = self.physics;
+        //enough map for the widest dino to reach whatever is in front of it
+        let map = self
+            .game_data
+            .map
+            .obstacles_until(simulation::x_at(until, &physics) + simulation::DINO_X_OFFSET + 2.0);
+
+        let grace = Duration::from_secs(self.config.reconnect_grace);
+        let mut crashed = vec![];
+        for (id, player) in self.player_data.iter_mut() {
+            if player.score != 0 {
+                continue;
             }
+            //a dropped player's dino keeps running without them, inputs sent on their return
+            //can't go back to before it
+            if let PlayerStatus::Disconnected(since) = player.status {
+                if since.elapsed() >= grace {
+                    let x = simulation::x_at(player.dino.checked_until(), &physics);
+                    crashed.push((*id, x.round().max(1.0) as u64));
+                    continue;
+                }
+            }
+            if let Some(dead_at) = player.dino.advance(until, map, &physics) {
+                let score = simulation::x_at(dead_at, &physics).round().max(1.0) as u64;
+                crashed.push((*id, score));
+            }
+        }
+
+        for (id, score) in crashed {
+            self.player_game_over(&id, score);
+        }
+    }
+
+    /// Hands a player's report to the anti cheat, only reports sent while the game is running
+    /// are checked.
+    fn validate_event(&mut self, id: &Uuid, event: PlayerEvent) {
+        let elapsed = if let Some(elapsed) = self.game_elapsed_time() {
+            elapsed as u128
+        } else {
+            return;
+        };
+
+        match self.anti_cheat.register_player_event(*id, event, elapsed) {
+            Ok(Some(violation)) => self.report_violation(id, violation),
+            Ok(None) => (),
+            Err(err) => println!("[session] anti cheat error for `{}`: {}", id, err),
+        }
+    }
+
+    /// Applies the configured `AntiCheatAction` once a player has reached the tolerated number
+    /// of violations.
+    fn report_violation(&mut self, id: &Uuid, violation: Violation) {
+        let player = if let Some(player) = self.player_data.get_mut(id) {
+            player
+        } else {
+            return;
+        };
+
+        player.violations += 1;
+        println!(
+            "[session] `{}` (id: `{}`) failed validation ({}/{}): {:?}",
+            player.username, id, player.violations, self.config.anti_cheat_tolerance, violation
         );
-        self.broadcast(user_id, TxData::UserGameOverBroadcast { username, score });
+        if player.violations != self.config.anti_cheat_tolerance {
+            return;
+        }
+
+        let action = self.config.anti_cheat_action;
+        if action >= AntiCheatAction::Flag {
+            player.flagged = true;
+        }
+        if action >= AntiCheatAction::Notify {
+            if let Some(sender) = self.senders.get(id) {
+                send_msg!(sender, TxData::InvalidationNotice);
+            }
+        }
+        if action >= AntiCheatAction::Kick {
+            self.kick_player(id);
+        }
+    }
+
+    fn kick_player(&mut self, id: &Uuid) {
+        if let Some(player) = self.player_data.remove(id) {
+            println!(
+                "[session] kicking `{}` (id: `{}`, addr: `{}`)",
+                player.username, player.id, player.addr
+            );
+            self.addr_map.remove(&player.addr);
+        }
+        self.bots.remove(id);
+        //the connection closes once its channels are dropped
+        self.receivers.remove(id);
+        self.senders.remove(id);
+    }
+
+    /// Carries out a command the host sent, telling everyone about it.
+    fn host_command(&mut self, host_id: &Uuid, command: HostCommand) {
+        let find = |s: &Self, username: &str| {
+            s.player_data
+                .values()
+                .find(|player| player.username == username)
+                .map(|player| player.id)
+        };
+
+        let refused = match &command {
+            HostCommand::Kick { username } | HostCommand::Ban { username } => {
+                match find(self, username) {
+                    Some(id) if &id == host_id => Some(ErrorCode::Unauthorized),
+                    Some(_) => None,
+                    None => Some(ErrorCode::UnknownPlayer),
+                }
+            }
+            //bots can't host
+            HostCommand::TransferHost { username } => match find(self, username) {
+                Some(id) if &id == host_id || self.bots.contains_key(&id) => {
+                    Some(ErrorCode::Unauthorized)
+                }
+                Some(_) => None,
+                None => Some(ErrorCode::UnknownPlayer),
+            },
+            HostCommand::AddBot { .. } => self.bot_refused(),
+            HostCommand::SetWaitTime { seconds } => {
+                if !matches!(self.status, SessionStatus::Waiting { .. }) {
+                    Some(ErrorCode::SessionNotAcceptingPlayers)
+                } else if !(1..=MAX_WAIT_TIME).contains(seconds) {
+                    Some(ErrorCode::InvalidRules)
+                } else {
+                    None
+                }
+            }
+            HostCommand::Lock { .. } => None,
+        };
+        if let Some(code) = refused {
+            self.send_error(host_id, code, "HostCommand");
+            return;
+        }
 
-        let active_players = self.player_data.values().filter(|v| v.score == 0).count();
-        Ok(active_players)
+        let host = self.player_data[host_id].username.clone();
+        //sent before anything happens so a kicked player still finds out why
+        self.emit(TxData::HostAction {
+            host,
+            command: command.clone(),
+        });
+
+        match command {
+            HostCommand::Kick { username } => {
+                if let Some(id) = find(self, &username) {
+                    self.kick_player(&id);
+                }
+            }
+            HostCommand::Ban { username } => {
+                if let Some(id) = find(self, &username) {
+                    self.banned_ips.insert(self.player_data[&id].addr.ip());
+                    self.banned_names.insert(username);
+                    self.kick_player(&id);
+                }
+            }
+            HostCommand::Lock { locked } => self.locked = locked,
+            HostCommand::SetWaitTime { seconds } => {
+                if let SessionStatus::Waiting { timeout, .. } = self.status {
+                    self.timers.remove(&timeout);
+                    self.wait_for_players(Duration::from_secs(seconds));
+                }
+            }
+            HostCommand::TransferHost { username } => {
+                if let Some(id) = find(self, &username) {
+                    self.host_id = id;
+                }
+            }
+            HostCommand::AddBot { level } => {
+                let _ = self.add_bot(level);
+            }
+        }
+    }
+
+    /// Goes back to the lobby, launching the game on its own after `duration`.
+    fn wait_for_players(&mut self, duration: Duration) {
+        let timeout = self.set_timeout(Self::start_waiting_game, duration);
+        self.status = SessionStatus::Waiting {
+            start_time: SystemTime::now(),
+            timeout,
+            duration,
+        };
+    }
+
+    /// Ends the lobby's wait, also generating the start of the map ahead of time.
+    pub fn start_waiting_game(&mut self) {
+        self.launch_game();
+        let chunk_size = self.config.map_chunk_size;
+        let _ = self.game_data.map.get_map(0, chunk_size - 1);
+    }
+
+    fn is_banned(&self, username: &str, addr: &SocketAddr) -> bool {
+        self.banned_names.contains(username) || self.banned_ips.contains(&addr.ip())
     }
 
     fn map_req(&mut self, player_id: &Uuid, idx: u32) {
@@ -398,15 +927,36 @@ impl Session {
         //     // return;
         // }
         //
-        match self.status {
-            _ => {
-                let idx = idx as usize;
-                let (from, to) = (idx as usize * 100, (idx as usize + 1) * 100 - 1); //0-99, 100-199, ..
+        if !self.map_chunk_reachable(idx) {
+            println!(
+                "[session] `{}` requested map chunk {} that can't be reached in `{}`",
+                player_id, idx, self.session_id
+            );
+            self.send_error(player_id, ErrorCode::MalformedMessage, "Map");
+            return;
+        }
+        let chunk = idx as usize;
+        let chunk_size = self.config.map_chunk_size;
+        let (from, to) = (chunk * chunk_size, (chunk + 1) * chunk_size - 1); //0-99, 100-199, ..
+
+        let map = self.game_data.map.get_map(from, to).to_vec();
+        // tx.send_to_addr(addr, TxData::Map { map })
+        send_msg!(self.sender(player_id).unwrap(), TxData::Map { map });
+        if let Some(player) = self.player_data.get_mut(player_id) {
+            player.map_chunks = player.map_chunks.max(idx + 1);
+        }
+    }
 
-                let map = self.game_data.map.get_map(from, to).to_vec();
-                // tx.send_to_addr(addr, TxData::Map { map })
-                send_msg!(self.senders.get(player_id).unwrap(), TxData::Map { map });
-            }
+    /// Whether a dino could get to chunk `idx` of the map before `max_duration` is up. Chunks
+    /// are handed out in order, so a made up index can't have the server generate a huge map.
+    fn map_chunk_reachable(&self, idx: u32) -> bool {
+        let Some(last) = (idx as usize * self.config.map_chunk_size).checked_sub(1) else {
+            return true;
+        };
+        let reach = simulation::x_at(self.config.max_duration as f64, &self.physics);
+        match self.game_data.map.generated().get(last) {
+            Some(((x, _), _)) => *x <= reach,
+            None => false, //nobody got the chunk before it yet
         }
     }
 
@@ -417,6 +967,7 @@ impl Session {
                 "[session] Unauthorized game launch request from  user id: `{}`.\nID mismatch: {}",
                 user_id, id_mismatch,
             );
+            self.send_error(user_id, ErrorCode::Unauthorized, "LaunchGame");
             return;
         }
 
@@ -426,7 +977,8 @@ impl Session {
     fn launch_game(&mut self) {
         if let SessionStatus::Waiting { timeout, .. } = self.status {
             self.timers.remove(&timeout);
-            let duration = Duration::from_secs(4);
+            //the client counts down from one less than this
+            let duration = Duration::from_secs(self.config.countdown + 1);
 
             self.status = SessionStatus::Countdown {
                 start: SystemTime::now(),
@@ -435,46 +987,77 @@ impl Session {
 
             self.emit(TxData::GameCountdownStart {
                 duration: duration.as_secs() as u32,
+                physics: self.physics,
             });
 
             self.set_timeout(
                 |s| {
                     s.status = SessionStatus::Active {
                         start_time: Instant::now(),
-                        max_duration: Duration::from_secs(30 * 60),
+                        max_duration: Duration::from_secs(s.config.max_duration),
                     };
                     s.emit(TxData::GameStart);
+                    if s.record_replays {
+                        s.replay = Some(Replay::new(s.session_name.clone(), s.seed(), s.physics));
+                    }
+                    s.set_interval(Self::check_collisions, COLLISION_CHECK_INTERVAL);
+                    s.set_interval(Self::push_leaderboard, LEADERBOARD_INTERVAL);
+                    if !s.bots.is_empty() {
+                        s.set_interval(Self::run_bots, BOT_INTERVAL);
+                    }
+                    if !s.ghosts.is_empty() {
+                        s.set_interval(Self::run_ghosts, GHOST_INTERVAL);
+                    }
                     println!("[session] Game just started!");
                 },
-                Duration::from_secs(3),
+                Duration::from_secs(self.config.countdown),
             );
         }
     }
 
+    /// Why `username` can't join from `addr`, if they can't.
+    pub fn refusal(
+        &self,
+        addr: &SocketAddr,
+        username: &str,
+        password: Option<&str>,
+    ) -> Option<ErrorCode> {
+        if !self.host_id.is_nil() && !matches!(self.status, SessionStatus::Waiting { .. }) {
+            Some(ErrorCode::SessionNotAcceptingPlayers)
+        } else if self.is_banned(username, addr) {
+            Some(ErrorCode::Banned)
+        } else if self.locked {
+            Some(ErrorCode::SessionNotAcceptingPlayers)
+        } else if !self.password_matches(password) {
+            Some(ErrorCode::WrongPassword)
+        } else if self.player_data.keys().len() >= self.config.max_users {
+            Some(ErrorCode::SessionFull)
+        } else if username.len() > self.config.max_username_len {
+            Some(ErrorCode::UsernameTooLong)
+        } else if self.username_exists(username) {
+            Some(ErrorCode::UsernameTaken)
+        } else {
+            None
+        }
+    }
+
     pub fn create_user(
         &mut self,
         addr: SocketAddr,
         channel: PlayerChannel,
         username: String,
+        password: Option<&str>,
     ) -> Result<(), PlayerChannel> {
-        if !self.host_id.is_nil() {
-            match self.status {
-                SessionStatus::Waiting { .. } => (),
-                _ => return Err(channel),
-            }
-        }
-
-        if self.player_data.keys().len() >= self.config.max_users
-            || username.len() > self.config.max_username_len
-            || self.username_exists(&username)
-        {
+        if let Some(code) = self.refusal(&addr, &username, password) {
             send_msg!(
                 channel.tx,
                 TxData::UserCreationResponse {
                     creation_succeeded: false,
                     user_id: None,
+                    token: None,
                 }
             );
+            send_msg!(channel.tx, TxData::error(code, "CreateUser"));
             return Err(channel);
         }
 
@@ -487,70 +1070,239 @@ impl Session {
                 duration: Duration::MAX,
             };
         };
-        self.player_data.insert(
-            id,
-            PlayerData {
-                id,
-                username,
-                score: 0,
-                addr,
-                status: PlayerStatus::Connected,
-                curr_tick: 0,
-            },
-        );
+        self.player_data
+            .insert(id, PlayerData::new(id, username, addr));
+        let _ = self.anti_cheat.register_player(id);
         self.addr_map.insert(addr, id);
+        let token = self.issue_token(&id);
 
         send_msg!(
             channel.tx,
             TxData::UserCreationResponse {
                 creation_succeeded: true,
                 user_id: Some(id),
+                token,
             }
         );
 
-        self.receivers.get_mut().insert(id, channel.rx);
+        self.receivers.insert(id, channel.rx);
         self.senders.insert(id, channel.tx);
 
         Ok(())
     }
 
-    fn username_exists(&self, username: &str) -> bool {
-        if let Some(_) = self.player_data.values().find(|d| &d.username == username) {
-            true
-        } else {
-            false
+    /// Adds a player the session controls, returning its username.
+    pub fn add_bot(&mut self, level: BotLevel) -> Result<String, ErrorCode> {
+        if let Some(code) = self.bot_refused() {
+            return Err(code);
         }
-    }
-
-    pub fn get_leaderboard(&self) -> Vec<(String, u64)> {
-        let mut leaderboard: Vec<(String, u64)> = self
-            .player_data
-            .keys()
-            .map(|k| {
-                let player_data = self.player_data.get(k).unwrap();
 
-                (
-                    player_data.username.clone(),
-                    if player_data.score > 0 {
-                        player_data.score
-                    } else {
-                        self.game_data.sync_score
-                    },
-                )
-            })
-            .collect();
+        let username = bot::NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .chain((1..).map(|n| format!("Dino{}", n)))
+            .find(|name| !self.username_exists(name))
+            .unwrap();
+        let id = Uuid::new_v4();
+        //bots never connect, no client can have this address
+        let addr = SocketAddr::from(([0, 0, 0, 0], 0));
+        self.player_data
+            .insert(id, PlayerData::new(id, username.clone(), addr));
+        let _ = self.anti_cheat.register_player(id);
+        self.bots.insert(id, Bot::new(level.skill()));
+        println!(
+            "[session] added {:?} bot `{}` to `{}`",
+            level, username, self.session_id
+        );
+        Ok(username)
+    }
 
-        leaderboard.sort_by_key(|d| d.1);
-        leaderboard
+    fn bot_refused(&self) -> Option<ErrorCode> {
+        if !matches!(
+            self.status,
+            SessionStatus::Uninit | SessionStatus::Waiting { .. }
+        ) {
+            Some(ErrorCode::SessionNotAcceptingPlayers)
+        } else if self.player_data.len() >= self.config.max_users {
+            Some(ErrorCode::SessionFull)
+        } else {
+            None
+        }
     }
 
-    #[inline(always)]
+    /// Moves every bot that's still running up to the current game time, their moves are
+    /// handled like the ones players send.
+    fn run_bots(&mut self) {
+        let t = if let Some(elapsed) = self.game_elapsed_time() {
+            elapsed * 0.001
+        } else {
+            return;
+        };
+        let physics = self.physics;
+
+        let mut moves = vec![];
+        for (id, bot) in self.bots.iter_mut() {
+            if self
+                .player_data
+                .get(id)
+                .is_none_or(|player| player.score != 0)
+            {
+                continue;
+            }
+            let events = bot.tick(t, &mut self.game_data.map, &physics);
+            moves.push((*id, events, bot.height(t, &physics)));
+        }
+
+        let x = simulation::x_at(t, &physics) as f32;
+        for (id, events, y) in moves {
+            for event in events {
+                self.on_game_event(&id, event);
+            }
+            self.on_broadcast_req(&id, y, x);
+        }
+    }
+
+    fn run_ghosts(&mut self) {
+        let t = if let Some(elapsed) = self.game_elapsed_time() {
+            elapsed
+        } else {
+            return;
+        };
+        let msgs: Vec<TxData> = self
+            .ghosts
+            .iter_mut()
+            .flat_map(|ghost| ghost.due(t))
+            .collect();
+        for msg in msgs {
+            self.emit(msg);
+        }
+    }
+
+    /// Signs a new resume token for the player, revoking the one they had.
+    fn issue_token(&mut self, id: &Uuid) -> Option<String> {
+        let player = self.player_data.get_mut(id)?;
+        player.token_id = rand::random();
+        Some(self.tokens.issue(&self.session_id, id, player.token_id))
+    }
+
+    /// The player `token` belongs to, if it's valid and hasn't been replaced by a newer one.
+    fn authenticate(&self, token: &str) -> Option<Uuid> {
+        let claims = match self.tokens.verify(&self.session_id, token) {
+            Ok(claims) => claims,
+            Err(err) => {
+                println!("[session] rejected token: {:?}", err);
+                return None;
+            }
+        };
+
+        match self.player_data.get(&claims.user_id) {
+            Some(player) if player.token_id == claims.token_id => Some(claims.user_id),
+            Some(_) => {
+                println!("[session] rejected revoked token of `{}`", claims.user_id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Attaches a read only channel that's sent everything the players see, without taking up
+    /// a player's spot.
+    pub fn add_spectator(
+        &mut self,
+        channel: PlayerChannel,
+        password: Option<&str>,
+    ) -> Result<(), PlayerChannel> {
+        let refused = if self.banned_ips.contains(&channel.addr.ip()) {
+            Some(ErrorCode::Banned)
+        } else if !self.password_matches(password) {
+            Some(ErrorCode::WrongPassword)
+        } else if self.spectators.len() >= self.config.max_spectators {
+            Some(ErrorCode::SessionFull)
+        } else if matches!(self.status, SessionStatus::Ended) {
+            Some(ErrorCode::SessionNotAcceptingPlayers)
+        } else {
+            None
+        };
+        if let Some(code) = refused {
+            send_msg!(channel.tx, TxData::SpectateResponse { succeeded: false });
+            send_msg!(channel.tx, TxData::error(code, "Spectate"));
+            return Err(channel);
+        }
+
+        let id = Uuid::new_v4();
+        println!(
+            "[session] `{}` is spectating `{}`",
+            channel.addr, self.session_id
+        );
+        send_msg!(channel.tx, TxData::SpectateResponse { succeeded: true });
+        send_msg!(channel.tx, self.resync(&id));
+        self.spectators.insert(id, channel);
+        Ok(())
+    }
+
+    fn username_exists(&self, username: &str) -> bool {
+        self.player_data.values().any(|d| d.username == username)
+    }
+
+    /// Everyone's score so far, best first. Players still running have the score of the
+    /// distance run until now, or until they dropped.
+    pub fn get_leaderboard(&self) -> Vec<LeaderboardEntry> {
+        let physics = &self.physics;
+        let mut players: Vec<(&PlayerData, u64, PlayerState)> = self
+            .player_data
+            .values()
+            .map(|player| match player.status {
+                _ if player.score > 0 => (player, player.score, PlayerState::Dead),
+                PlayerStatus::Connected => (player, self.game_data.sync_score, PlayerState::Alive),
+                PlayerStatus::Disconnected(_) => {
+                    let x = simulation::x_at(player.dino.checked_until(), physics);
+                    (player, x.round() as u64, PlayerState::Disconnected)
+                }
+            })
+            .collect();
+        players.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.username.cmp(&b.0.username)));
+
+        let scores: Vec<u64> = players.iter().map(|(_, score, _)| *score).collect();
+        players
+            .into_iter()
+            .zip(ranks(&scores))
+            .map(|((player, score, state), rank)| LeaderboardEntry {
+                rank,
+                username: player.username.clone(),
+                score,
+                state,
+            })
+            .collect()
+    }
+
+    fn push_leaderboard(&mut self) {
+        self.emit(TxData::Leaderboard {
+            leaderboard: self.get_leaderboard(),
+        });
+    }
+
+    /// Final scores of the players whose game has ended, leaving out the ones the anti cheat
+    /// flagged.
+    pub fn finished_players(&self) -> Vec<(String, u64)> {
+        self.player_data
+            .values()
+            .filter(|player| player.score > 0 && !player.flagged)
+            .map(|player| (player.username.clone(), player.score))
+            .collect()
+    }
+
+    /// Usernames of the players the anti cheat has flagged.
+    pub fn get_flagged(&self) -> Vec<String> {
+        self.player_data
+            .values()
+            .filter(|player| player.flagged)
+            .map(|player| player.username.clone())
+            .collect()
+    }
+
+    #[inline(always)]
     fn game_elapsed_time(&self) -> Option<f64> {
-        if let SessionStatus::Active {
-            start_time,
-            max_duration,
-        } = self.status
-        {
+        if let SessionStatus::Active { start_time, .. } = self.status {
             Some(Instant::elapsed(&start_time).as_secs_f64() * 1000.0)
         } else {
             None
@@ -587,28 +1339,103 @@ impl Session {
             .collect()
     }
 
+    /// Address of the host's current connection, `None` while they're disconnected.
     pub fn get_host_addr(&self) -> Option<SocketAddr> {
-        if let Some(player) = &self.player_data.get(&self.host_id) {
-            Some(player.addr)
+        self.player_data
+            .get(&self.host_id)
+            .filter(|player| matches!(player.status, PlayerStatus::Connected))
+            .map(|player| player.addr)
+    }
+
+    /// Moves pending messages from the player channels into the inbox, registering the task's
+    /// waker with every channel that has nothing left to read.
+    ///
+    /// Returns `Poll::Ready` if at least one message was received or a connection closed.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut closed = vec![];
+        let received_before = self.inbox.len();
+
+        for (id, rx) in &mut self.receivers {
+            let mut read_count = 0;
+            while read_count < MAX_READ_COUNT {
+                match rx.poll_next_unpin(cx) {
+                    Poll::Ready(Some(msg)) => self.inbox.push((*id, parse_msg!(msg))),
+                    Poll::Ready(None) => {
+                        closed.push(*id);
+                        break;
+                    }
+                    Poll::Pending => break,
+                }
+                read_count += 1;
+            }
+        }
+
+        for (id, spectator) in &mut self.spectators {
+            let mut read_count = 0;
+            while read_count < MAX_READ_COUNT {
+                match spectator.rx.poll_next_unpin(cx) {
+                    Poll::Ready(Some(msg)) => self.inbox.push((*id, msg)),
+                    Poll::Ready(None) => {
+                        closed.push(*id);
+                        break;
+                    }
+                    Poll::Pending => break,
+                }
+                read_count += 1;
+            }
+        }
+
+        for id in closed {
+            //spectators just leave, players get some time to come back
+            if self.spectators.remove(&id).is_some() {
+                continue;
+            }
+            self.receivers.remove(&id);
+            self.dropped.push(id);
+        }
+
+        if self.inbox.len() > received_before || !self.dropped.is_empty() {
+            Poll::Ready(())
         } else {
-            None
+            Poll::Pending
+        }
+    }
+
+    /// Queues a message the executor read from `addr` before handing its channel over, so it's
+    /// handled like everything that arrives after.
+    pub fn forward(&mut self, addr: SocketAddr, msg: RxData) {
+        let player = self
+            .player_data
+            .values()
+            .find(|player| player.addr == addr && self.receivers.contains_key(&player.id))
+            .map(|player| player.id);
+        let spectator = || {
+            self.spectators
+                .iter()
+                .find(|(_, spectator)| spectator.addr == addr)
+                .map(|(id, _)| *id)
+        };
+        if let Some(id) = player.or_else(spectator) {
+            self.inbox.push((id, msg));
         }
     }
 
     fn process_messages(&mut self) {
-        let mut receivers = self.receivers.take();
-        for (id, rx) in &mut receivers {
-            while let Ok(Some(msg)) = rx.try_next() {
-                let msg = parse_msg!(msg);
+        for (id, msg) in std::mem::take(&mut self.inbox) {
+            if self.spectators.contains_key(&id) {
+                self.on_spectator_recv(&id, msg);
+            } else {
                 self.on_recv(&id, msg);
             }
         }
-        self.receivers.set(receivers);
+        for id in std::mem::take(&mut self.dropped) {
+            self.on_user_con_close(&id);
+        }
     }
 
     fn curr_score(&self, start_time: Instant) -> u64 {
         let elapsed = start_time.elapsed().as_secs_f64();
-        ((INITIAL_X_VEL as f64 * elapsed) + (0.5 * X_ACC as f64 * elapsed.powi(2))).round() as u64
+        simulation::x_at(elapsed, &self.physics).round() as u64
     }
 
     pub fn game_loop(&mut self) -> bool {
@@ -623,96 +1450,401 @@ impl Session {
             max_duration,
         } = self.status
         {
-            // self.game_data.sync_score = self.curr_score(start_time);
-            if start_time.elapsed() > max_duration {
-                true
-            } else {
-                false
+            self.game_data.sync_score = self.curr_score(start_time);
+            if start_time.elapsed() >= max_duration || self.decided() {
+                self.end_game();
+            }
+        }
+        self.has_finished
+    }
+
+    /// Whether the game can't go on: everyone crashed, or only one player is left running in
+    /// elimination mode. Nobody is left to watch once only bots are running.
+    fn decided(&self) -> bool {
+        let running: Vec<&PlayerData> = self
+            .player_data
+            .values()
+            .filter(|player| player.score == 0)
+            .collect();
+        running
+            .iter()
+            .all(|player| self.bots.contains_key(&player.id))
+            || (self.config.elimination && self.player_data.len() > 1 && running.len() == 1)
+    }
+
+    /// Stops the game and sends out the results, the session is closed unless enough players
+    /// vote for a rematch within `rematch_window` seconds.
+    fn end_game(&mut self) {
+        //whoever is still running scores what they've run so far
+        let mut survivors = FxHashSet::default();
+        if let SessionStatus::Active { start_time, .. } = self.status {
+            let score = self.curr_score(start_time).max(1);
+            let physics = self.physics;
+            for player in self.player_data.values_mut().filter(|p| p.score == 0) {
+                player.score = match player.status {
+                    PlayerStatus::Connected => {
+                        survivors.insert(player.id);
+                        score
+                    }
+                    PlayerStatus::Disconnected(_) => {
+                        let x = simulation::x_at(player.dino.checked_until(), &physics);
+                        x.round().max(1.0) as u64
+                    }
+                };
             }
+        }
+
+        self.timers.clear();
+        self.status = SessionStatus::Ended;
+        let results = self.results(&survivors);
+        println!(
+            "[session] game of `{}` is over, {} players finished",
+            self.session_id,
+            results.len()
+        );
+        let replay = self.replay.take().map(|mut replay| {
+            replay.results = results.clone();
+            let id = replay.id;
+            self.finished_replays.push(replay);
+            id
+        });
+        if !results.is_empty() {
+            self.finished_matches
+                .push(self.match_record(results.clone(), replay));
+        }
+        self.emit(TxData::GameResults { results, replay });
+
+        if self.config.rematch_window == 0 {
+            self.has_finished = true;
+            return;
+        }
+
+        self.emit(TxData::GameEnded {
+            rematch_window: self.config.rematch_window,
+        });
+        self.set_timeout(
+            |s| s.has_finished = true,
+            Duration::from_secs(self.config.rematch_window),
+        );
+    }
+
+    /// Adds what a player sent to the replay of the running game.
+    fn record(&mut self, id: &Uuid, input: Input) {
+        let t = self.game_elapsed_time();
+        if let (Some(replay), Some(player), Some(t)) =
+            (&mut self.replay, self.player_data.get(id), t)
+        {
+            replay.record(t, &player.username, input);
+        }
+    }
+
+    fn match_record(&self, results: Vec<PlayerResult>, replay: Option<Uuid>) -> MatchRecord {
+        MatchRecord {
+            id: Uuid::new_v4(),
+            session_name: self.session_name.clone(),
+            ended_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            seed: self.seed(),
+            rules: MatchRules {
+                physics: self.physics,
+                elimination: self.config.elimination,
+                max_duration: self.config.max_duration,
+            },
+            results,
+            bots: self
+                .bots
+                .keys()
+                .filter_map(|id| self.player_data.get(id))
+                .map(|player| player.username.clone())
+                .collect(),
+            replay,
+        }
+    }
+
+    /// Matches that ended since the last call, for the match store.
+    pub fn take_matches(&mut self) -> Vec<MatchRecord> {
+        std::mem::take(&mut self.finished_matches)
+    }
+
+    /// Replays of the games that ended since the last call.
+    pub fn take_replays(&mut self) -> Vec<Replay> {
+        std::mem::take(&mut self.finished_replays)
+    }
+
+    /// Everyone's final standing, best first.
+    fn results(&self, survivors: &FxHashSet<Uuid>) -> Vec<PlayerResult> {
+        let mut players: Vec<&PlayerData> = self.player_data.values().collect();
+        players.sort_by(|a, b| b.score.cmp(&a.score).then(a.username.cmp(&b.username)));
+
+        let scores: Vec<u64> = players.iter().map(|player| player.score).collect();
+        players
+            .into_iter()
+            .zip(ranks(&scores))
+            .map(|(player, rank)| {
+                let distance = player.score as f64;
+                PlayerResult {
+                    rank,
+                    username: player.username.clone(),
+                    score: player.score,
+                    survival_time: simulation::time_at_x(distance, &self.physics),
+                    distance,
+                    survived: survivors.contains(&player.id),
+                    flagged: player.flagged,
+                }
+            })
+            .collect()
+    }
+
+    /// Counts `player_id`'s vote, starting the rematch once most connected players want one.
+    fn rematch_vote(&mut self, player_id: &Uuid, same_map: bool) {
+        if !matches!(self.status, SessionStatus::Ended) || self.has_finished {
+            self.send_error(player_id, ErrorCode::GameNotOver, "Rematch");
+            return;
+        }
+
+        self.rematch_votes.insert(*player_id);
+        if *player_id == self.host_id {
+            self.rematch_same_map = same_map;
+        }
+        let (votes, needed) = (self.rematch_votes.len(), self.rematch_votes_needed());
+        self.emit(TxData::RematchVote {
+            username: self.player_data[player_id].username.clone(),
+            votes: votes as u32,
+            needed: needed as u32,
+        });
+
+        if votes >= needed {
+            self.rematch();
+        }
+    }
+
+    fn rematch_votes_needed(&self) -> usize {
+        let connected = self
+            .player_data
+            .values()
+            .filter(|player| matches!(player.status, PlayerStatus::Connected))
+            .filter(|player| !self.bots.contains_key(&player.id))
+            .count();
+        connected / 2 + 1
+    }
+
+    /// Sends everyone back to the lobby with a fresh game, the players and the host stay.
+    fn rematch(&mut self) {
+        let seed = if self.rematch_same_map {
+            self.seed()
+        } else {
+            rand::random()
+        };
+        println!("[session] `{}` is having a rematch", self.session_id);
+
+        self.timers.clear();
+        self.rematch_votes.clear();
+        self.rematch_same_map = false;
+        //ghosts only fit the map they were recorded on
+        if seed == self.seed() {
+            self.ghosts.iter_mut().for_each(Ghost::reset);
         } else {
-            false
+            self.ghosts.clear();
         }
+        self.game_data = GameData::new(self.physics, seed);
+        self.anti_cheat = new_anti_cheat(self.physics);
+        for player in self.player_data.values_mut() {
+            player.reset();
+            let _ = self.anti_cheat.register_player(player.id);
+        }
+        self.bots.values_mut().for_each(Bot::reset);
+        self.wait_for_players(self.wait_time);
+
+        //timers of players that dropped before the game ended were cleared along with the rest
+        self.remove_dropped_players();
+        let grace = Duration::from_secs(self.config.reconnect_grace);
+        self.set_timeout(Self::remove_dropped_players, grace);
+        self.set_timeout(Self::migrate_host, grace);
+
+        self.emit(TxData::RematchStart {
+            seed,
+            wait_time: self.wait_time.as_secs(),
+        });
     }
 
+    /// Moves the player `token` belongs to over to `channel`, the token they get back replaces
+    /// the one they logged in with so a leaked token stops working once its owner logs in.
     pub fn login_user(
         &mut self,
         addr: SocketAddr,
-        user_id: Uuid,
+        token: &str,
         channel: PlayerChannel,
     ) -> Result<(), PlayerChannel> {
-        if let Some(player) = self.player_data.get_mut(&user_id) {
+        if let Some(user_id) = self.authenticate(token) {
+            let token = self.issue_token(&user_id);
+            let player = self.player_data.get_mut(&user_id).unwrap();
             player.connect();
             player.addr = addr;
             self.addr_map.insert(addr, user_id);
-            let result = send_msg!(channel.tx, TxData::LoginResponse { succeeded: true });
+            send_msg!(
+                channel.tx,
+                TxData::LoginResponse {
+                    succeeded: true,
+                    token,
+                }
+            );
+            send_msg!(channel.tx, self.resync(&user_id));
             // tx.send_to_addr(addr, TxData::LoginResponse { succeeded: true });
-            self.receivers.get_mut().insert(user_id, channel.rx);
+            self.receivers.insert(user_id, channel.rx);
             self.senders.insert(user_id, channel.tx);
             return Ok(());
         }
-        // tx.send_to_addr(addr, TxData::LoginResponse { succeeded: false });
+        send_msg!(
+            channel.tx,
+            TxData::LoginResponse {
+                succeeded: false,
+                token: None,
+            }
+        );
+        send_msg!(channel.tx, TxData::error(ErrorCode::Unauthorized, "Login"));
         Err(channel)
     }
 
-    pub fn on_user_con_close(&mut self, addr: SocketAddr) -> bool {
-        let user_id = if let Some(id) = self
-            .player_data
-            .values()
-            .filter(|p| p.addr == addr)
-            .map(|p| p.id)
-            .next()
-        {
-            id
+    /// The player's connection closed, they have `reconnect_grace` seconds to log back in
+    /// before they're out of the game.
+    fn on_user_con_close(&mut self, user_id: &Uuid) {
+        //logged in again before the old connection's close got here
+        if self.receivers.contains_key(user_id) {
+            return;
+        }
+        let player = if let Some(player) = self.player_data.get_mut(user_id) {
+            player
         } else {
-            return false;
+            return;
         };
+        println!(
+            "[session] `{}` (id: `{}`, addr: `{}`) just closed connection.",
+            player.username, player.id, player.addr
+        );
+        player.disconnect();
+        let addr = player.addr;
 
         self.addr_map.remove(&addr);
-        self.receivers.get_mut().remove(&user_id);
-        self.senders.remove(&user_id);
+        self.receivers.remove(user_id);
+        self.senders.remove(user_id);
+
+        //votes are only counted for the players that are still around
+        if matches!(self.status, SessionStatus::Ended) && !self.has_finished {
+            self.rematch_votes.remove(user_id);
+            if !self.rematch_votes.is_empty()
+                && self.rematch_votes.len() >= self.rematch_votes_needed()
+            {
+                self.rematch();
+            }
+        }
 
-        let start_time = match self.status {
-            SessionStatus::Active { start_time, .. } => start_time,
-            SessionStatus::Countdown { .. } | SessionStatus::Waiting { .. } => {
-                let player_data = self.player_data.get_mut(&user_id).unwrap();
-                println!(
-                    "[session] `{}` (id: `{}`, addr: `{}`) just closed connection.",
-                    player_data.username, player_data.id, player_data.addr
-                );
-                player_data.disconnect();
-                return false;
+        //running games check for dropped players along with collisions
+        if let SessionStatus::Waiting { .. } = self.status {
+            let grace = Duration::from_secs(self.config.reconnect_grace);
+            if *user_id == self.host_id {
+                self.set_timeout(Self::migrate_host, grace);
+            } else {
+                self.set_timeout(Self::remove_dropped_players, grace);
             }
-            _ => return false,
-        };
+        }
+    }
 
-        let curr_score = self.curr_score(start_time);
-        if let Some(player) = self.player_data.get_mut(&user_id) {
-            player.score = curr_score;
+    /// Hands the lobby to the player that's been in it the longest if the host didn't come back.
+    fn migrate_host(&mut self) {
+        if !matches!(self.status, SessionStatus::Waiting { .. }) {
+            return;
+        }
+        match self.player_data.get(&self.host_id) {
+            Some(host) if !self.grace_expired(host) => return,
+            _ => (),
+        }
+
+        let successor = self
+            .player_data
+            .values()
+            .filter(|player| matches!(player.status, PlayerStatus::Connected))
+            .filter(|player| !self.bots.contains_key(&player.id))
+            .min_by_key(|player| player.joined);
+        if let Some(successor) = successor {
             println!(
-                "[session] `{}` (id: `{}`, addr: `{}`) just closed connection. Final score: `{}`",
-                player.username, player.id, player.addr, player.score
+                "[session] host of `{}` didn't come back, `{}` (id: `{}`) is the new host",
+                self.session_id, successor.username, successor.id
             );
-            player.disconnect();
-            //   self.set_timeout(
-            //     |s, tx| {
-            //       if let Some(player) = s.player_data.get_mut(&user_id) {
-            //         println!("[session] One minute past last connection from `{}`(`{}`), removing user...", player.addr, player.id);
-            //       s.player_data.remove(&user_id);
-            // }
+            let username = successor.username.clone();
+            self.host_id = successor.id;
+            self.emit(TxData::HostChanged { username });
+            //the old host is just another dropped player now
+            self.remove_dropped_players();
+        }
+    }
+
+    fn grace_expired(&self, player: &PlayerData) -> bool {
+        match player.status {
+            PlayerStatus::Disconnected(since) => {
+                since.elapsed() >= Duration::from_secs(self.config.reconnect_grace)
+            }
+            PlayerStatus::Connected => false,
+        }
+    }
 
-            //}
-            //, Duration::from_secs(60)
-            //);
-            return self.player_data.is_empty();
+    /// Frees the spots of players that left the lobby, the host keeps theirs.
+    fn remove_dropped_players(&mut self) {
+        if !matches!(self.status, SessionStatus::Waiting { .. }) {
+            return;
         }
 
-        false
+        let dropped: Vec<Uuid> = self
+            .player_data
+            .values()
+            .filter(|player| player.id != self.host_id && self.grace_expired(player))
+            .map(|player| player.id)
+            .collect();
+        for id in dropped {
+            if let Some(player) = self.player_data.remove(&id) {
+                println!(
+                    "[session] `{}` (id: `{}`) didn't come back, removing them",
+                    player.username, id
+                );
+            }
+        }
     }
 
-    pub fn shutdown(&mut self, tx: &mut TransmissionQueue) {
-        self.player_data
+    /// Everything a client needs to catch up with the session after logging in.
+    fn resync(&mut self, user_id: &Uuid) -> TxData {
+        let (status, time) = self.get_status();
+        let chunks = self
+            .player_data
+            .get(user_id)
+            .map_or(0, |player| player.map_chunks) as usize;
+        let map = match chunks * self.config.map_chunk_size {
+            0 => vec![],
+            len => self.game_data.map.get_map(0, len - 1).to_vec(),
+        };
+        let players = self
+            .player_data
             .values()
-            .for_each(|player| tx.close_con(player.addr));
+            .filter(|player| &player.id != user_id)
+            .map(|player| PlayerSnapshot {
+                username: player.username.clone(),
+                pos: player.last_pos,
+                score: player.score,
+                connected: matches!(player.status, PlayerStatus::Connected),
+            })
+            .collect();
+
+        TxData::Resync {
+            status,
+            time,
+            elapsed: self.game_elapsed_time(),
+            map,
+            leaderboard: self.get_leaderboard(),
+            players,
+        }
+    }
+
+    pub fn shutdown(&mut self) {
         self.has_finished = true;
         println!("[session] `{}` shutting down...", self.id());
     }
@@ -723,9 +1855,48 @@ pub struct GameData {
     sync_score: u64, //score of every player is same until they lose. This will be the highest score.
 }
 
+impl GameData {
+    fn new(physics: SessionPhysicsConfig, seed: u32) -> Self {
+        Self {
+            map: GameMap::new(
+                physics.initial_vel,
+                physics.x_acc,
+                physics.gravity,
+                physics.jump_vel,
+                seed,
+            ),
+            sync_score: 0,
+        }
+    }
+}
+
+/// Ranks of `scores` sorted best first, equal scores share the rank of the first of them
+/// (1, 2, 2, 4), like on the leaderboard.
+fn ranks(scores: &[u64]) -> Vec<u32> {
+    let mut ranks: Vec<u32> = Vec::with_capacity(scores.len());
+    for (i, score) in scores.iter().enumerate() {
+        let rank = match i {
+            0 => 1,
+            _ if scores[i - 1] == *score => ranks[i - 1],
+            _ => i as u32 + 1,
+        };
+        ranks.push(rank);
+    }
+    ranks
+}
+
+fn new_anti_cheat(physics: SessionPhysicsConfig) -> AntiCheat {
+    AntiCheat::new(AntiCheatConfig {
+        physics,
+        position_tolerance: POSITION_TOLERANCE,
+        position_tolerance_ratio: POSITION_TOLERANCE_RATIO,
+        latency_tolerance: LATENCY_TOLERANCE,
+    })
+}
+
 pub enum PlayerStatus {
     Connected,
-    Disconnected,
+    Disconnected(Instant),
 }
 
 pub struct PlayerData {
@@ -735,7 +1906,13 @@ pub struct PlayerData {
     addr: SocketAddr,
     status: PlayerStatus,
     curr_tick: u64, //a monotonic counter (counted by client and server separately) to keep chronological order of broadcast requests
-                    //
+    dino: DinoSim,
+    violations: u32,
+    flagged: bool, //shown next to the player's score on the leaderboard
+    token_id: u64, //of the only resume token that's still valid
+    last_pos: [f32; 2],
+    map_chunks: u32, //chunks requested so far, sent again on resync
+    joined: Instant,
 }
 
 impl PlayerData {
@@ -747,12 +1924,595 @@ impl PlayerData {
             score: 0,
             status: PlayerStatus::Connected,
             curr_tick: 0,
+            dino: DinoSim::new(),
+            violations: 0,
+            flagged: false,
+            token_id: 0,
+            last_pos: [0.0, 0.0],
+            map_chunks: 0,
+            joined: Instant::now(),
         }
     }
     pub fn disconnect(&mut self) {
-        self.status = PlayerStatus::Disconnected
+        self.status = PlayerStatus::Disconnected(Instant::now())
     }
     pub fn connect(&mut self) {
         self.status = PlayerStatus::Connected
     }
+    /// Forgets everything about the last game.
+    fn reset(&mut self) {
+        self.score = 0;
+        self.curr_tick = 0;
+        self.dino = DinoSim::new();
+        self.violations = 0;
+        self.flagged = false;
+        self.last_pos = [0.0, 0.0];
+        self.map_chunks = 0;
+    }
+}
+
+#[test]
+fn interval_timers_are_rescheduled() {
+    let mut session = Session::new(
+        "timers".to_owned(),
+        SessionConfig {
+            max_users: 2,
+            ..SessionConfig::default()
+        },
+        0,
+        TokenSigner::new("", 60),
+    );
+    assert_eq!(session.next_deadline(), None);
+
+    session.set_interval(|_| (), Duration::from_secs(60));
+    session.set_timeout(|_| (), Duration::ZERO);
+    let now = Instant::now();
+    session
+        .timers
+        .values_mut()
+        .for_each(|timer| timer.due = now);
+    session.exec_timers();
+
+    //the timeout is gone, the interval got pushed back instead of staying overdue forever
+    assert_eq!(session.timers.len(), 1);
+    assert!(session.next_deadline().unwrap() > now + Duration::from_secs(59));
+}
+
+#[test]
+fn refused_players_are_told_why() {
+    use futures_channel::mpsc::unbounded;
+
+    let mut session = Session::new(
+        "errors".to_owned(),
+        SessionConfig {
+            max_users: 2,
+            max_username_len: 5,
+            ..SessionConfig::default()
+        },
+        0,
+        TokenSigner::new("", 60),
+    );
+    let mut join = |username: &str| {
+        let (tx, mut tx_rx) = unbounded();
+        let (_rx_tx, rx) = unbounded();
+        let addr = "127.0.0.1:1234".parse().unwrap();
+        let joined = session
+            .create_user(
+                addr,
+                PlayerChannel { tx, rx, addr },
+                username.to_owned(),
+                None,
+            )
+            .is_ok();
+        let mut error = None;
+        while let Ok(Some(msg)) = tx_rx.try_next() {
+            if let TxData::Error { code, request } = msg {
+                assert_eq!(request.as_deref(), Some("CreateUser"));
+                error = Some(code);
+            }
+        }
+        (joined, error)
+    };
+
+    assert_eq!(join("dino"), (true, None));
+    assert_eq!(join("dino"), (false, Some(ErrorCode::UsernameTaken)));
+    assert_eq!(
+        join("pterodactyl"),
+        (false, Some(ErrorCode::UsernameTooLong))
+    );
+    assert_eq!(join("rex"), (true, None));
+    assert_eq!(join("trex"), (false, Some(ErrorCode::SessionFull)));
+}
+
+#[test]
+fn dropped_players_can_come_back_within_the_grace_period() {
+    use futures_channel::mpsc::unbounded;
+    use futures_util::task::noop_waker_ref;
+
+    let mut session = Session::new(
+        "reconnect".to_owned(),
+        SessionConfig::default(),
+        0,
+        TokenSigner::new("", 60),
+    );
+    let addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+    let join = |session: &mut Session, username: &str| {
+        let (tx, mut tx_rx) = unbounded();
+        let (rx_tx, rx) = unbounded();
+        let _ = session.create_user(
+            addr,
+            PlayerChannel { tx, rx, addr },
+            username.to_owned(),
+            None,
+        );
+        match tx_rx.try_next() {
+            Ok(Some(TxData::UserCreationResponse {
+                user_id: Some(id),
+                token: Some(token),
+                ..
+            })) => (id, token, rx_tx),
+            _ => panic!("`{}` couldn't join", username),
+        }
+    };
+    let (host, _, _host_conn) = join(&mut session, "host");
+    let (id, token, conn) = join(&mut session, "dino");
+
+    drop(conn);
+    let _ = session.poll_recv(&mut Context::from_waker(noop_waker_ref()));
+    session.game_loop();
+    assert!(matches!(
+        session.player_data[&id].status,
+        PlayerStatus::Disconnected(_)
+    ));
+    session.remove_dropped_players();
+    assert!(session.player_data.contains_key(&id));
+
+    let (tx, mut tx_rx) = unbounded();
+    let (_conn, rx) = unbounded();
+    assert!(session
+        .login_user(addr, &token, PlayerChannel { tx, rx, addr })
+        .is_ok());
+    assert!(matches!(
+        tx_rx.try_next(),
+        Ok(Some(TxData::LoginResponse {
+            succeeded: true,
+            ..
+        }))
+    ));
+    match tx_rx.try_next() {
+        Ok(Some(TxData::Resync { players, .. })) => {
+            assert_eq!(players.len(), 1);
+            assert_eq!(players[0].username, "host");
+        }
+        _ => panic!("no resync after logging in"),
+    }
+
+    //the host's spot is kept, everyone else's is freed once the grace period is over
+    let long_ago = Instant::now() - Duration::from_secs(SessionConfig::default().reconnect_grace);
+    for player in session.player_data.values_mut() {
+        player.status = PlayerStatus::Disconnected(long_ago);
+    }
+    session.remove_dropped_players();
+    assert!(session.player_data.contains_key(&host));
+    assert!(!session.player_data.contains_key(&id));
+}
+
+#[cfg(test)]
+fn test_session(config: SessionConfig) -> Session {
+    Session::new("lunch".to_owned(), config, 0, TokenSigner::new("", 60))
+}
+
+/// A connection from `addr` and the receiving end of what the session sends it.
+#[cfg(test)]
+fn test_channel(addr: &str) -> (PlayerChannel, UnboundedReceiver<TxData>) {
+    use futures_channel::mpsc::unbounded;
+
+    let (tx, outbox) = unbounded();
+    let (_, rx) = unbounded();
+    let addr = addr.parse().unwrap();
+    (PlayerChannel { tx, rx, addr }, outbox)
+}
+
+/// Joins as `username` from `port` on localhost, returning the player's id.
+#[cfg(test)]
+fn join(session: &mut Session, port: u16, username: &str) -> Uuid {
+    let (channel, _) = test_channel(&format!("127.0.0.1:{}", port));
+    let addr = channel.addr;
+    assert!(session
+        .create_user(addr, channel, username.to_owned(), None)
+        .is_ok());
+    session.addr_map[&addr]
+}
+
+#[test]
+fn only_reachable_map_chunks_are_sent() {
+    let mut session = test_session(SessionConfig {
+        max_duration: 10,
+        map_chunk_size: 2,
+        ..SessionConfig::default()
+    });
+    let (channel, mut outbox) = test_channel("127.0.0.1:1");
+    assert!(session
+        .create_user(channel.addr, channel, "a".to_owned(), None)
+        .is_ok());
+    let id = session.addr_map[&"127.0.0.1:1".parse().unwrap()];
+    let mut sent = || std::iter::from_fn(|| outbox.try_next().ok().flatten()).last();
+
+    session.map_req(&id, u32::MAX);
+    assert!(matches!(sent(), Some(TxData::Error { .. })));
+    assert_eq!(session.player_data[&id].map_chunks, 0);
+
+    let mut chunks = 0;
+    loop {
+        session.map_req(&id, chunks);
+        match sent() {
+            Some(TxData::Map { .. }) => chunks += 1,
+            _ => break,
+        }
+    }
+    let reach = simulation::x_at(10.0, &session.physics);
+    let map = session.game_data.map.generated();
+    assert!(map[chunks as usize * 2 - 3].0 .0 <= reach);
+    assert!(map[chunks as usize * 2 - 1].0 .0 > reach);
+    assert_eq!(session.player_data[&id].map_chunks, chunks);
+
+    match session.resync(&id) {
+        TxData::Resync { map, .. } => assert_eq!(map.len(), chunks as usize * 2),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn spectators_get_the_same_map_chunks_as_players() {
+    let mut session = test_session(SessionConfig::default());
+    let (channel, mut outbox) = test_channel("127.0.0.1:1");
+    assert!(session.add_spectator(channel, None).is_ok());
+    let id = *session.spectators.keys().next().unwrap();
+    let session_id = session.session_id;
+    let map = |index: u32| {
+        let json = format!(
+            r#"{{"type":"Map","sessionId":"{}","userId":"{}","index":{}}}"#,
+            session_id,
+            Uuid::nil(),
+            index
+        );
+        serde_json::from_str(&json).unwrap()
+    };
+
+    let mut sent =
+        || -> Vec<TxData> { std::iter::from_fn(|| outbox.try_next().ok().flatten()).collect() };
+    sent();
+
+    session.on_spectator_recv(&id, map(u32::MAX));
+    session.on_spectator_recv(&id, map(0));
+    let sent = sent();
+    assert!(matches!(
+        sent.as_slice(),
+        [TxData::Error { .. }, TxData::Map { .. }]
+    ));
+    assert_eq!(session.game_data.map.generated().len(), 100);
+}
+
+#[test]
+fn spectators_dont_take_player_spots() {
+    let mut session = test_session(SessionConfig {
+        max_users: 1,
+        max_spectators: 1,
+        ..SessionConfig::default()
+    });
+    let channel = || test_channel("127.0.0.1:1234").0;
+
+    assert!(session.add_spectator(channel(), None).is_ok());
+    assert!(session.add_spectator(channel(), None).is_err());
+    join(&mut session, 1234, "dino");
+    assert_eq!(session.get_leaderboard().len(), 1);
+}
+
+#[test]
+fn passwords_are_checked() {
+    let mut session = test_session(SessionConfig::default()).with_access(
+        SessionVisibility::Private,
+        "ABC234".to_owned(),
+        Some("pizza"),
+    );
+    let channel = || test_channel("127.0.0.1:1234").0;
+    let addr = "127.0.0.1:1234".parse().unwrap();
+
+    assert!(session
+        .create_user(addr, channel(), "a".to_owned(), None)
+        .is_err());
+    assert!(session
+        .create_user(addr, channel(), "a".to_owned(), Some("pasta"))
+        .is_err());
+    assert!(session
+        .create_user(addr, channel(), "a".to_owned(), Some("pizza"))
+        .is_ok());
+    assert!(session.add_spectator(channel(), None).is_err());
+    assert!(session.add_spectator(channel(), Some("pizza")).is_ok());
+}
+
+#[test]
+fn hosts_can_ban_and_lock() {
+    let mut session = test_session(SessionConfig::default());
+    //bans are by ip, so everyone joins from an address of their own
+    let join = |session: &mut Session, addr: &str, username: &str| {
+        let (channel, _) = test_channel(addr);
+        session
+            .create_user(channel.addr, channel, username.to_owned(), None)
+            .is_ok()
+    };
+
+    assert!(join(&mut session, "127.0.0.1:1", "host"));
+    assert!(join(&mut session, "127.0.0.2:1", "troll"));
+    let host = session.host_id;
+
+    session.host_command(
+        &host,
+        HostCommand::Ban {
+            username: "troll".to_owned(),
+        },
+    );
+    assert!(!session.username_exists("troll"));
+    assert!(!join(&mut session, "127.0.0.2:2", "not a troll"));
+    assert!(!join(&mut session, "127.0.0.3:1", "troll"));
+
+    session.host_command(&host, HostCommand::Lock { locked: true });
+    assert!(!join(&mut session, "127.0.0.4:1", "late"));
+    session.host_command(&host, HostCommand::Lock { locked: false });
+    assert!(join(&mut session, "127.0.0.4:1", "late"));
+}
+
+#[test]
+fn hosts_that_dont_come_back_are_replaced() {
+    let mut session = test_session(SessionConfig::default());
+    let host = join(&mut session, 1, "host");
+    join(&mut session, 2, "first");
+    join(&mut session, 3, "second");
+
+    session.on_user_con_close(&host);
+    session.migrate_host();
+    assert_eq!(
+        session.host_id, host,
+        "the host still has time to come back"
+    );
+
+    let left = Instant::now() - Duration::from_secs(session.config.reconnect_grace + 1);
+    session.player_data.get_mut(&host).unwrap().status = PlayerStatus::Disconnected(left);
+    session.migrate_host();
+    assert_eq!(session.player_data[&session.host_id].username, "first");
+    assert!(!session.player_data.contains_key(&host));
+}
+
+#[test]
+fn rematches_keep_the_lobby_together() {
+    let mut session = test_session(SessionConfig::default());
+    let host = join(&mut session, 1, "host");
+    let guest = join(&mut session, 2, "guest");
+    let seed = session.seed();
+
+    session.end_game();
+    assert!(!session.game_loop(), "the session waits for rematch votes");
+    session.rematch_vote(&host, true);
+    assert!(matches!(session.status, SessionStatus::Ended));
+    session.rematch_vote(&guest, false);
+
+    assert!(matches!(session.status, SessionStatus::Waiting { .. }));
+    assert_eq!(session.host_id, host);
+    assert_eq!(session.player_data.len(), 2);
+    assert_eq!(session.seed(), seed, "the host asked for the same map");
+    assert!(session.rematch_votes.is_empty());
+}
+
+#[test]
+fn elimination_games_end_with_the_last_one_running() {
+    let mut session = test_session(SessionConfig {
+        elimination: true,
+        rematch_window: 0,
+        ..SessionConfig::default()
+    });
+    let (channel, mut inbox) = test_channel("127.0.0.1:1");
+    assert!(session
+        .create_user(channel.addr, channel, "a".to_owned(), None)
+        .is_ok());
+    let ids: Vec<Uuid> = ["b", "c", "d"]
+        .iter()
+        .enumerate()
+        .map(|(i, username)| join(&mut session, i as u16 + 2, username))
+        .collect();
+    session.status = SessionStatus::Active {
+        start_time: Instant::now() - Duration::from_secs(10),
+        max_duration: Duration::from_secs(60),
+    };
+
+    session.player_game_over(&ids[0], 30);
+    session.player_game_over(&ids[1], 30);
+    assert!(!session.game_loop());
+    session.player_game_over(&ids[2], 50);
+    assert!(session.game_loop(), "only `a` is left");
+
+    let results = std::iter::from_fn(|| inbox.try_next().ok().flatten())
+        .find_map(|msg| match msg {
+            TxData::GameResults { results, .. } => Some(results),
+            _ => None,
+        })
+        .unwrap();
+    let standings: Vec<(&str, u32, bool)> = results
+        .iter()
+        .map(|result| (result.username.as_str(), result.rank, result.survived))
+        .collect();
+    assert_eq!(
+        standings,
+        [
+            ("a", 1, true),
+            ("d", 2, false),
+            ("b", 3, false),
+            ("c", 3, false)
+        ]
+    );
+}
+
+#[test]
+fn leaderboard_is_ranked_best_first() {
+    let mut session = test_session(SessionConfig::default());
+    let ids: Vec<Uuid> = ["a", "b", "c", "d"]
+        .iter()
+        .enumerate()
+        .map(|(i, username)| join(&mut session, i as u16 + 1, username))
+        .collect();
+    session.status = SessionStatus::Active {
+        start_time: Instant::now() - Duration::from_secs(10),
+        max_duration: Duration::from_secs(60),
+    };
+
+    session.player_game_over(&ids[1], 30);
+    session.player_game_over(&ids[2], 30);
+    session.player_data.get_mut(&ids[3]).unwrap().disconnect();
+    assert!(!session.game_loop());
+
+    let leaderboard = session.get_leaderboard();
+    let standings: Vec<(&str, u32, PlayerState)> = leaderboard
+        .iter()
+        .map(|entry| (entry.username.as_str(), entry.rank, entry.state))
+        .collect();
+    assert_eq!(
+        standings,
+        [
+            ("a", 1, PlayerState::Alive),
+            ("b", 2, PlayerState::Dead),
+            ("c", 2, PlayerState::Dead),
+            ("d", 4, PlayerState::Disconnected),
+        ]
+    );
+    assert!(leaderboard[0].score > 30, "live score of `a`");
+}
+
+#[test]
+fn bots_play_like_everyone_else() {
+    let mut session = test_session(SessionConfig {
+        rematch_window: 0,
+        ..SessionConfig::default()
+    });
+    let (channel, mut inbox) = test_channel("127.0.0.1:1");
+    assert!(session
+        .create_user(channel.addr, channel, "a".to_owned(), None)
+        .is_ok());
+    let host = session.host_id;
+
+    for level in [BotLevel::Hard, BotLevel::Easy, BotLevel::Easy] {
+        session.host_command(&host, HostCommand::AddBot { level });
+    }
+    session.host_command(
+        &host,
+        HostCommand::TransferHost {
+            username: "Rex".to_owned(),
+        },
+    );
+    assert_eq!(session.host_id, host, "bots can't host");
+    session.host_command(
+        &host,
+        HostCommand::Kick {
+            username: "Pebble".to_owned(),
+        },
+    );
+    let mut usernames = session.get_usernames();
+    usernames.sort();
+    assert_eq!(usernames, ["Rex", "Spike", "a"]);
+
+    session.status = SessionStatus::Active {
+        start_time: Instant::now() - Duration::from_millis(500),
+        max_duration: Duration::from_secs(60),
+    };
+    session.run_bots();
+    let sent: Vec<TxData> = std::iter::from_fn(|| inbox.try_next().ok().flatten()).collect();
+    let mut moved: Vec<String> = sent
+        .into_iter()
+        .filter_map(|msg| match msg {
+            TxData::Broadcast { username, .. } => Some(username),
+            _ => None,
+        })
+        .collect();
+    moved.sort();
+    assert_eq!(moved, ["Rex", "Spike"]);
+
+    let alive = session
+        .get_leaderboard()
+        .iter()
+        .filter(|entry| entry.state == PlayerState::Alive)
+        .count();
+    assert_eq!(alive, 3);
+
+    //nobody is left to watch the bots
+    session.player_game_over(&host, 4);
+    assert!(session.game_loop());
+}
+
+#[test]
+fn reported_crashes_score_where_the_server_last_saw_the_dino() {
+    let mut session = test_session(SessionConfig::default());
+    let id = join(&mut session, 1, "a");
+    session.status = SessionStatus::Active {
+        start_time: Instant::now() - Duration::from_secs(10),
+        max_duration: Duration::from_secs(60),
+    };
+
+    //the server hasn't simulated any of the 10 seconds yet
+    session.client_game_over(&id);
+    assert_eq!(session.player_data[&id].score, 1);
+}
+
+#[test]
+fn dropped_players_dinos_keep_running() {
+    let mut session = test_session(SessionConfig::default());
+    join(&mut session, 1, "host");
+    let id = join(&mut session, 2, "dino");
+    session.status = SessionStatus::Active {
+        start_time: Instant::now() - Duration::from_secs(60),
+        max_duration: Duration::from_secs(120),
+    };
+
+    session.player_data.get_mut(&id).unwrap().disconnect();
+    session.check_collisions();
+    assert!(
+        session.player_data[&id].score > 0,
+        "nobody jumped over the first obstacle"
+    );
+}
+
+#[test]
+fn rematch_votes_are_counted_among_those_still_here() {
+    let mut session = test_session(SessionConfig::default());
+    let ids: Vec<Uuid> = ["a", "b", "c", "d", "e"]
+        .iter()
+        .enumerate()
+        .map(|(i, username)| join(&mut session, i as u16 + 1, username))
+        .collect();
+    let leave = |session: &mut Session, id: &Uuid| {
+        session.receivers.remove(id);
+        session.on_user_con_close(id);
+    };
+    session.end_game();
+
+    session.rematch_vote(&ids[0], false);
+    session.rematch_vote(&ids[1], false);
+    leave(&mut session, &ids[1]);
+    assert_eq!(session.rematch_votes.len(), 1);
+    session.rematch_vote(&ids[2], false);
+    assert!(matches!(session.status, SessionStatus::Ended));
+
+    //two of the three left want a rematch
+    leave(&mut session, &ids[4]);
+    assert!(matches!(session.status, SessionStatus::Waiting { .. }));
+}
+
+#[test]
+fn quick_play_rematches_skip_the_lobby() {
+    let mut session = test_session(SessionConfig::default()).without_lobby();
+    let ids = [join(&mut session, 1, "a"), join(&mut session, 2, "b")];
+    session.start_waiting_game();
+    session.end_game();
+
+    session.rematch_vote(&ids[0], true);
+    session.rematch_vote(&ids[1], true);
+    assert!(!session.game_loop());
+    assert!(matches!(session.status, SessionStatus::Countdown { .. }));
 }
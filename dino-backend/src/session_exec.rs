@@ -1,27 +1,37 @@
-use futures_channel::mpsc::{Receiver, Sender, UnboundedReceiver, UnboundedSender};
-use futures_util::Stream;
+use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use futures_util::{future, StreamExt};
 // use tokio::sync::mpsc::{self, UnboundedReceiver};
-use rayon::prelude::*;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
-use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
+use tokio::task::JoinHandle;
+use tokio::time;
 use uuid::Uuid;
 
 use rustc_hash::FxHashMap;
 
 use std::net::SocketAddr;
-use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
 
-use crate::config_options::{ConfigOptions, SessionConfig, SessionExecConfig};
+use crate::config_options::{ConfigOptions, SessionConfig};
 use crate::obstacles::Obstacle;
-use crate::parse_msg;
 use crate::send_msg;
 use crate::session::PlayerChannel;
 use crate::session::Session;
 use crate::session::SessionStatus;
-
-#[derive(Deserialize)]
+use crate::session::MAX_READ_COUNT;
+
+use crate::auth::TokenSigner;
+use crate::bot::BotLevel;
+use crate::daily_challenge::{self, DailyLeaderboards};
+use crate::matchmaking::Matchmaker;
+use crate::rating::{RatedPlayer, RatingChange, Ratings};
+use crate::replay::{self, Ghost, GhostIndex, Replay};
+use crate::storage::{MatchRecord, MatchRules, MatchStore, ScoreEntry};
+use crate::validator::SessionPhysicsConfig;
+
+#[derive(Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum QueryType {
     Sessions,
@@ -33,33 +43,97 @@ pub enum QueryType {
         #[serde(rename = "sessionId")]
         session_id: Uuid,
     },
+    DailyLeaderBoard {
+        date: Option<String>, //YYYY-MM-DD in UTC, today if not given, none after today
+    },
+    /// Best runs of all time, across every finished match.
+    TopScores {
+        limit: Option<usize>,
+    },
+    PlayerHistory {
+        username: String,
+        limit: Option<usize>,
+    },
+    RecentMatches {
+        limit: Option<usize>,
+    },
+    /// Highest rated players first.
+    Ratings {
+        limit: Option<usize>,
+    },
+    RatingHistory {
+        username: String,
+        limit: Option<usize>,
+    },
+}
+
+/// Entries a stored match query returns if it doesn't ask for a number, and the most it can.
+const DEFAULT_QUERY_LIMIT: usize = 10;
+const MAX_QUERY_LIMIT: usize = 100;
+
+fn query_limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(DEFAULT_QUERY_LIMIT).min(MAX_QUERY_LIMIT)
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Clone, JsonSchema)]
 pub enum SessionStatusSimplified {
     Waiting,
     Busy,
     Ended,
 }
 
-#[derive(Serialize, Clone)]
+/// Id, name, status and players of a public session. Players are listed with their rating,
+/// `None` until their first rated match.
+pub type SessionListing = (
+    Uuid,
+    String,
+    SessionStatusSimplified,
+    Vec<(String, Option<f64>)>,
+);
+
+#[derive(Serialize, Clone, JsonSchema)]
 #[serde(tag = "type")]
 pub enum QueryResponseType {
     Sessions {
-        sessions: Vec<(Uuid, String, SessionStatusSimplified, Vec<String>)>,
+        sessions: Vec<SessionListing>,
     },
     LeaderBoard {
         #[serde(rename = "sessionId")]
         session_id: Uuid,
-        scores: Vec<(String, u64)>,
+        scores: Vec<LeaderboardEntry>,
+        flagged: Vec<String>, //usernames that failed validation
     },
     SessionStatus {
         status: &'static str, //refers to the enum,
         time: i64,
+        seed: u32,
+    },
+    DailyLeaderBoard {
+        date: String,
+        seed: u32,
+        scores: Vec<(String, u64)>,
+    },
+    TopScores {
+        scores: Vec<ScoreEntry>,
+    },
+    PlayerHistory {
+        username: String,
+        matches: Vec<MatchRecord>, //newest first
+    },
+    RecentMatches {
+        matches: Vec<MatchRecord>, //newest first
+    },
+    Ratings {
+        players: Vec<RatedPlayer>,
+    },
+    RatingHistory {
+        username: String,
+        rating: Option<f64>,
+        history: Vec<RatingChange>, //newest first
     },
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Clone, JsonSchema)]
 #[serde(tag = "type")]
 pub enum TxData {
     QueryResponse {
@@ -72,6 +146,9 @@ pub enum TxData {
         creation_succeeded: bool,
         #[serde(rename = "sessionId")]
         session_id: Option<Uuid>,
+        seed: Option<u32>, //the map seed, kept to 32 bits so it survives being a JS number
+        #[serde(rename = "joinCode")]
+        join_code: Option<String>,
     },
 
     UserCreationResponse {
@@ -79,20 +156,72 @@ pub enum TxData {
         creation_succeeded: bool,
         #[serde(rename = "userId")]
         user_id: Option<Uuid>,
+        token: Option<String>, //proves who the player is from now on, see `auth`
     },
 
     LoginResponse {
         succeeded: bool,
+        token: Option<String>, //replaces the one logged in with
     },
 
+    SpectateResponse {
+        succeeded: bool,
+    },
+    /// The replay follows as the messages players got during the game.
+    WatchReplayResponse {
+        succeeded: bool,
+    },
+
+    /// Sent to everyone every second while the game is running.
+    Leaderboard {
+        leaderboard: Vec<LeaderboardEntry>,
+    },
+
+    /// Final standings, sent to everyone once the game is over.
+    GameResults {
+        results: Vec<PlayerResult>,
+        replay: Option<Uuid>, //id to watch the game again with, if it was recorded
+    },
+
+    /// The game is over, votes for a rematch are taken for `rematchWindow` seconds.
+    GameEnded {
+        #[serde(rename = "rematchWindow")]
+        rematch_window: u64,
+    },
+    RematchVote {
+        username: String,
+        votes: u32,
+        needed: u32,
+    },
+    /// Everyone's back in the lobby, the map is generated from `seed` again.
+    RematchStart {
+        seed: u32,
+        #[serde(rename = "waitTime")]
+        wait_time: u64,
+    },
+
+    QuickPlayResponse {
+        queued: bool,
+    },
+    /// Sent to every player of a quick play match right after their `UserCreationResponse`, the
+    /// game launches right away.
+    MatchFound {
+        #[serde(rename = "sessionId")]
+        session_id: Uuid,
+        seed: u32,
+    },
+
+    /// `ghost` is set on the messages of a recorded run raced against, see `GhostChoice`.
     Broadcast {
         username: String,
         pos: [f32; 2],
         tick: u64,
+        ghost: bool,
     },
 
     GameCountdownStart {
         duration: u32,
+        physics: SessionPhysicsConfig, //the session's own, see `SessionRules`
     },
 
     GameStart,
@@ -104,6 +233,7 @@ pub enum TxData {
     UserGameOverBroadcast {
         username: String,
         score: u64,
+        ghost: bool,
     },
 
     UserGameOver {
@@ -115,6 +245,7 @@ pub enum TxData {
     GameEvent {
         username: String,
         event: GameEvent,
+        ghost: bool,
     },
 
     Event {
@@ -123,12 +254,150 @@ pub enum TxData {
         timestamp: f64,
         pos: [f64; 2],
         vel: [f64; 2],
+        ghost: bool,
     },
 
     InvalidationNotice,
+
+    /// Sent after every successful `Login` so a reconnecting client can pick up where the game
+    /// is.
+    Resync {
+        status: &'static str,
+        time: i64,                             //as in `QueryResponseType::SessionStatus`
+        elapsed: Option<f64>,                  //ms since the game started
+        map: Vec<((f64, f64), Vec<Obstacle>)>, //every chunk the player already requested
+        leaderboard: Vec<LeaderboardEntry>,
+        players: Vec<PlayerSnapshot>, //everyone else
+    },
+
+    /// The host left the lobby and didn't come back in time, `username` took over.
+    HostChanged {
+        username: String,
+    },
+
+    /// A host command went through, sent to the whole lobby.
+    HostAction {
+        host: String,
+        command: HostCommand,
+    },
+
+    Error {
+        code: ErrorCode,
+        request: Option<String>, //`type` of the refused message, if it could be read
+    },
+}
+
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct PlayerSnapshot {
+    pub username: String,
+    pub pos: [f32; 2], //last reported
+    pub score: u64,    //0 while still running
+    pub connected: bool,
+}
+
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct LeaderboardEntry {
+    pub rank: u32, //shared by players with the same score
+    pub username: String,
+    pub score: u64,
+    pub state: PlayerState,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub enum PlayerState {
+    Alive,
+    Dead,
+    Disconnected, //still running, without them until they're back
+}
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PlayerResult {
+    pub rank: u32, //shared by players with the same score
+    pub username: String,
+    pub score: u64,
+    #[serde(rename = "survivalTime")]
+    pub survival_time: f64, //seconds
+    pub distance: f64,
+    pub survived: bool, //still running when the game ended
+    pub flagged: bool,
+}
+
+impl TxData {
+    pub fn error(code: ErrorCode, request: &str) -> Self {
+        TxData::Error {
+            code,
+            request: Some(request.to_owned()),
+        }
+    }
+}
+
+/// Why a request was refused.
+#[derive(Serialize, Clone, Copy, PartialEq, Debug, JsonSchema)]
+pub enum ErrorCode {
+    UsernameTaken,
+    UsernameTooLong,
+    SessionFull,
+    SessionNotAcceptingPlayers,
+    UnknownSession,
+    Unauthorized,
+    MalformedMessage,
+    RateLimited,
+    AlreadyInSession,
+    ServerFull, //no room for another session
+    InvalidRules,
+    WrongPassword,
+    UnknownPlayer,
+    Banned,
+    GameNotOver,
+    UnknownReplay,
+    DailyChallengeDisabled, //the server has no `daily_key`
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+/// What a session's host can do to the lobby.
+#[derive(Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(tag = "type")]
+pub enum HostCommand {
+    Kick {
+        username: String,
+    },
+    /// Kicks the player and keeps their username and address out for as long as the session
+    /// lives.
+    Ban {
+        username: String,
+    },
+    Lock {
+        locked: bool,
+    },
+    SetWaitTime {
+        seconds: u64,
+    },
+    TransferHost {
+        username: String,
+    },
+    /// Adds a player the server controls, named by the server.
+    AddBot {
+        level: BotLevel,
+    },
+}
+
+/// Who can find and join a session.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default, JsonSchema)]
+pub enum SessionVisibility {
+    #[default]
+    Public, //listed, joined by id or join code
+    Unlisted, //not listed, joined by id or join code
+    Private,  //not listed, joined by join code only
+}
+
+/// Characters of a join code, without the ones that are easily mistaken for each other.
+const JOIN_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const JOIN_CODE_LEN: usize = 6;
+
+fn normalize_join_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+#[derive(Deserialize, Serialize, Clone, JsonSchema)]
 #[serde(tag = "type")]
 pub enum GameEvent {
     Jump { pos: f32 },
@@ -137,8 +406,9 @@ pub enum GameEvent {
 }
 
 // parsed data from ChannelData::Message
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(tag = "type")]
+#[allow(dead_code)] //ids clients still send, the session knows them from the sender's channel
 pub enum RxData {
     Query {
         query: QueryType,
@@ -150,26 +420,81 @@ pub enum RxData {
         session_name: String,
         #[serde(rename = "waitTime")]
         wait_time: u64,
+        seed: Option<u32>, //random if not given
+        rules: Option<SessionRules>,
+        #[serde(default)]
+        visibility: SessionVisibility,
+        password: Option<String>, //asked of everyone joining or spectating
+    },
+
+    DailyChallenge {
+        username: String,
     },
 
     CreateUser {
         #[serde(rename = "sessionId")]
         session_id: Uuid,
         username: String,
+        password: Option<String>,
+    },
+
+    /// `CreateUser` by the session's join code rather than its id, the only way into private
+    /// sessions.
+    JoinWithCode {
+        #[serde(rename = "joinCode")]
+        join_code: String,
+        username: String,
+        password: Option<String>,
     },
 
     Login {
         #[serde(rename = "sessionId")]
         session_id: Uuid,
-        #[serde(rename = "userId")]
-        user_id: Uuid,
+        token: String,
     },
 
     LaunchGame {
         #[serde(rename = "sessionId")]
         session_id: Uuid,
-        #[serde(rename = "userId")]
-        user_id: Uuid,
+        token: String,
+    },
+
+    /// Vote for playing again once the game is over, the host's vote decides whether it's on
+    /// the same map.
+    Rematch {
+        #[serde(rename = "sessionId")]
+        session_id: Uuid,
+        token: String,
+        #[serde(rename = "sameMap", default)]
+        same_map: bool,
+    },
+
+    HostCommand {
+        #[serde(rename = "sessionId")]
+        session_id: Uuid,
+        token: String,
+        command: HostCommand,
+    },
+
+    /// Watch a recorded game, `speed` times as fast.
+    WatchReplay {
+        #[serde(rename = "replayId")]
+        replay_id: Uuid,
+        speed: Option<f64>,
+    },
+
+    /// Wait for a quick play match instead of picking a session.
+    QuickPlay {
+        username: String,
+    },
+    LeaveQueue,
+
+    /// Watch a session without playing, spectators can only request the map and query the
+    /// session's status.
+    Spectate {
+        #[serde(rename = "sessionId")]
+        session_id: Uuid,
+        password: Option<String>,
     },
 
     BroadcastReq {
@@ -214,12 +539,96 @@ pub enum RxData {
     GameOver {
         #[serde(rename = "sessionId")]
         session_id: Uuid,
-        #[serde(rename = "userId")]
-        user_id: Uuid,
+        token: String,
+    },
+}
+
+impl RxData {
+    /// The message's `type`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RxData::Query { .. } => "Query",
+            RxData::CreateSession { .. } => "CreateSession",
+            RxData::DailyChallenge { .. } => "DailyChallenge",
+            RxData::CreateUser { .. } => "CreateUser",
+            RxData::JoinWithCode { .. } => "JoinWithCode",
+            RxData::Login { .. } => "Login",
+            RxData::LaunchGame { .. } => "LaunchGame",
+            RxData::Spectate { .. } => "Spectate",
+            RxData::QuickPlay { .. } => "QuickPlay",
+            RxData::WatchReplay { .. } => "WatchReplay",
+            RxData::LeaveQueue => "LeaveQueue",
+            RxData::HostCommand { .. } => "HostCommand",
+            RxData::Rematch { .. } => "Rematch",
+            RxData::BroadcastReq { .. } => "BroadcastReq",
+            RxData::ValidationData { .. } => "ValidationData",
+            RxData::Map { .. } => "Map",
+            RxData::GameEvent { .. } => "GameEvent",
+            RxData::Event { .. } => "Event",
+            RxData::GameOver { .. } => "GameOver",
+        }
+    }
+}
+
+/// Gameplay settings a host can pick for their session, anything left out is the server's.
+#[derive(Deserialize, JsonSchema)]
+pub struct SessionRules {
+    pub physics: Option<SessionPhysicsConfig>,
+    pub countdown: Option<u64>,
+    #[serde(rename = "maxDuration")]
+    pub max_duration: Option<u64>,
+    /// End the game as soon as only one player is left running.
+    pub elimination: Option<bool>,
+    /// Recorded runs on the same map to race against, only found if the session has a `seed`.
+    #[serde(default)]
+    pub ghosts: Vec<GhostChoice>,
+}
+
+/// Which recorded run a ghost replays.
+#[derive(Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum GhostChoice {
+    /// The best run anyone had.
+    Best,
+    PersonalBest {
+        username: String,
     },
 }
 
-#[derive(Deserialize)]
+/// Most ghosts a session can race against.
+const MAX_GHOSTS: usize = 4;
+
+impl SessionRules {
+    /// `config` with the rules applied, or why they were rejected.
+    pub fn apply(&self, mut config: SessionConfig) -> Result<SessionConfig, &'static str> {
+        if let Some(physics) = self.physics {
+            physics.check_bounds()?;
+            config.physics = physics;
+        }
+        if let Some(countdown) = self.countdown {
+            if !(1..=30).contains(&countdown) {
+                return Err("countdown must be between 1 and 30 seconds");
+            }
+            config.countdown = countdown;
+        }
+        if let Some(max_duration) = self.max_duration {
+            //hosts can shorten games, not hold on to a session for longer than the server allows
+            if !(10..=config.max_duration).contains(&max_duration) {
+                return Err("max duration must be at least 10 seconds and within the server's");
+            }
+            config.max_duration = max_duration;
+        }
+        if let Some(elimination) = self.elimination {
+            config.elimination = elimination;
+        }
+        if self.ghosts.len() > MAX_GHOSTS {
+            return Err("too many ghosts");
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
 pub enum PlayerMove {
     None,
     Up,
@@ -235,137 +644,142 @@ pub enum ChannelData {
     Disconnect(SocketAddr),
 }
 
-pub struct TransmissionQueue {
-    queue: Vec<(SocketAddr, TxData)>,
-    closable: Vec<SocketAddr>,
-}
-
-impl TransmissionQueue {
-    pub fn new() -> Self {
-        Self {
-            queue: Vec::with_capacity(128),
-            closable: vec![],
-        }
-    }
-
-    pub fn send_to_addr(&mut self, addr: SocketAddr, msg: TxData) {
-        self.queue.push((addr, msg))
-    }
-
-    pub fn close_con(&mut self, addr: SocketAddr) {
-        self.closable.push(addr)
-    }
-
-    fn clear_queue(&mut self) {
-        self.queue.clear();
-    }
-
-    fn clear_closable(&mut self) {
-        self.closable.clear();
-    }
-}
-
-pub type PeerMap =
-    Arc<Mutex<FxHashMap<SocketAddr, (UnboundedReceiver<WsMessage>, UnboundedSender<WsMessage>)>>>;
 pub type UserSessionMap = FxHashMap<SocketAddr, Option<Uuid>>;
 
 pub struct SessionExecutor {
     sessions: FxHashMap<Uuid, Session>,
     session_hosts: FxHashMap<SocketAddr, Uuid>, //key: host address, value: session id
+    host_addrs: FxHashMap<Uuid, SocketAddr>,    //the other way around, to spot host changes
     channel_rx: mpsc::Receiver<ChannelData>,
-    // peer_map: PeerMap,
     user_session_map: UserSessionMap,
-    tx_queue: TransmissionQueue,
     closable_sessions: Vec<Uuid>,
     config: ConfigOptions,
     channels: FxHashMap<SocketAddr, PlayerChannel>,
-    receivers: FxHashMap<Uuid, Vec<mpsc::UnboundedReceiver<RxData>>>,
+    inbox: Vec<(SocketAddr, Option<RxData>)>, //`None` once the connection handler hung up
+    daily_sessions: FxHashMap<Uuid, u32>, //key: session id, value: the day it's the challenge of
+    daily_leaderboards: DailyLeaderboards,
+    tokens: TokenSigner,
+    daily_keys: Option<TokenSigner>,
+    join_codes: FxHashMap<String, Uuid>,
+    matchmaker: Matchmaker,
+    matches: Box<dyn MatchStore>,
+    ratings: Ratings,
+    ghosts: GhostIndex,
+    unsaved_ghosts: FxHashMap<Uuid, MatchRecord>, //key: replay id, indexed once it's saved
+    saved_replays: UnboundedReceiver<(Uuid, bool)>, //replay id and whether it could be saved
+    saved_replays_tx: UnboundedSender<(Uuid, bool)>,
+    playbacks: FxHashMap<SocketAddr, JoinHandle<()>>, //replays being streamed to connections
 }
 
 impl SessionExecutor {
-    pub fn new(config: ConfigOptions) -> Self {
-        //FIXME: not sure what the buffer size should be
-        let (channel_tx, channel_rx) = mpsc::channel(1024);
-
+    pub fn new_with_channel(
+        rx: mpsc::Receiver<ChannelData>,
+        config: ConfigOptions,
+        matches: Box<dyn MatchStore>,
+    ) -> Self {
         let mut sessions = FxHashMap::default();
+        let (saved_replays_tx, saved_replays) = futures_channel::mpsc::unbounded();
+        let tokens = TokenSigner::new(&config.server.token_key, config.server.token_ttl);
+        let daily_key = &config.server.daily_key;
+        let daily_keys = (!daily_key.is_empty()).then(|| TokenSigner::new(daily_key, 0));
+        //the rules of quick play, matches with anything else aren't rated
+        let default_rules = MatchRules {
+            physics: config.session.physics,
+            elimination: config.session.elimination,
+            max_duration: config.session.max_duration,
+        };
 
         if config.session_exec.dummy_sessions {
             sessions.insert(
                 Uuid::new_v4(),
-                Session::new("idk".to_string(), config.session),
+                Session::new("idk".to_string(), config.session, rand::random(), tokens),
             );
             sessions.insert(
                 Uuid::new_v4(),
-                Session::new("idk2".to_string(), config.session),
+                Session::new("idk2".to_string(), config.session, rand::random(), tokens),
             );
             sessions.insert(
                 Uuid::new_v4(),
-                Session::new("id3k".to_string(), config.session),
+                Session::new("id3k".to_string(), config.session, rand::random(), tokens),
             );
             sessions.insert(
                 Uuid::new_v4(),
-                Session::new("1idk".to_string(), config.session),
+                Session::new("1idk".to_string(), config.session, rand::random(), tokens),
             );
             sessions.insert(
                 Uuid::new_v4(),
-                Session::new("id4k".to_string(), config.session),
+                Session::new("id4k".to_string(), config.session, rand::random(), tokens),
             );
+            for session in sessions.values_mut() {
+                for _ in 0..config.session_exec.dummy_session_bots {
+                    let _ = session.add_bot(config.session_exec.dummy_bot_level);
+                }
+            }
         }
+
         Self {
             sessions,
             session_hosts: FxHashMap::default(),
-            channel_rx,
-            // peer_map: PeerMap::new(Mutex::new(FxHashMap::default())),
+            host_addrs: FxHashMap::default(),
+            channel_rx: rx,
             user_session_map: UserSessionMap::default(),
-            tx_queue: TransmissionQueue::new(),
             closable_sessions: vec![],
             config,
             channels: FxHashMap::default(),
-            receivers: FxHashMap::default(),
+            inbox: vec![],
+            daily_sessions: FxHashMap::default(),
+            daily_leaderboards: DailyLeaderboards::default(),
+            tokens,
+            daily_keys,
+            join_codes: FxHashMap::default(),
+            matchmaker: Matchmaker::default(),
+            ratings: Ratings::from_store(matches.as_ref(), default_rules),
+            ghosts: GhostIndex::from_store(matches.as_ref()),
+            matches,
+            unsaved_ghosts: FxHashMap::default(),
+            saved_replays,
+            saved_replays_tx,
+            playbacks: FxHashMap::default(),
         }
     }
 
-    pub fn new_with_channel(rx: mpsc::Receiver<ChannelData>, config: ConfigOptions) -> Self {
-        let mut sessions = FxHashMap::default();
+    /// Drives every session until the main channel is closed.
+    ///
+    /// The executor sleeps until a connection is opened or closed, a message arrives on any
+    /// player channel or the next session timer is due, so an idle server doesn't burn CPU.
+    pub async fn run(mut self) {
+        loop {
+            let deadline = self
+                .sessions
+                .values()
+                .filter_map(Session::next_deadline)
+                .chain(self.matchmaking_deadline())
+                .min();
+
+            let channels = &mut self.channels;
+            let sessions = &mut self.sessions;
+            let inbox = &mut self.inbox;
+            let channel_msg = tokio::select! {
+                msg = self.channel_rx.recv() => Some(msg),
+                _ = future::poll_fn(|cx| poll_receivers(channels, sessions, inbox, cx)) => None,
+                _ = time::sleep_until(deadline.unwrap_or_else(Instant::now).into()),
+                    if deadline.is_some() => None,
+            };
+
+            match channel_msg {
+                Some(Some(msg)) => self.process_channel_msg(msg),
+                Some(None) => break,
+                None => (),
+            }
 
-        if config.session_exec.dummy_sessions {
-            sessions.insert(
-                Uuid::new_v4(),
-                Session::new("idk".to_string(), config.session),
-            );
-            sessions.insert(
-                Uuid::new_v4(),
-                Session::new("idk2".to_string(), config.session),
-            );
-            sessions.insert(
-                Uuid::new_v4(),
-                Session::new("id3k".to_string(), config.session),
-            );
-            sessions.insert(
-                Uuid::new_v4(),
-                Session::new("1idk".to_string(), config.session),
-            );
-            sessions.insert(
-                Uuid::new_v4(),
-                Session::new("id4k".to_string(), config.session),
-            );
-        }
-
-        Self {
-            sessions,
-            session_hosts: FxHashMap::default(),
-            channel_rx: rx,
-            // peer_map: PeerMap::new(Mutex::new(FxHashMap::default())),
-            user_session_map: UserSessionMap::default(),
-            tx_queue: TransmissionQueue::new(),
-            closable_sessions: vec![],
-            config,
-            channels: FxHashMap::default(),
-            receivers: FxHashMap::default(),
+            self.poll_main_channel();
+            self.poll_sub_channels();
+            self.run_matchmaking();
+            self.run_sessions();
         }
     }
 
-    pub fn poll_main_channel(&mut self) {
+    fn poll_main_channel(&mut self) {
         let mut recv_count = 0;
         while let Ok(msg) = self.channel_rx.try_recv() {
             self.process_channel_msg(msg);
@@ -382,57 +796,55 @@ impl SessionExecutor {
             //this is not called anymore :)
             ChannelData::Connect { addr, tx, rx } => {
                 println!("[session exec] `{}` just connected.", &addr);
-                // self.peer_map.lock().unwrap().insert(addr, (rx, tx));
                 self.user_session_map.insert(addr, None);
                 self.channels.insert(addr, PlayerChannel { tx, rx, addr });
             }
             ChannelData::Disconnect(addr) => {
                 println!("[session exec] `{}` closed connection :(", addr);
-                // self.peer_map.lock().unwrap().remove(&addr);
                 self.channels.remove(&addr);
-                // if let Some(Some(s_id)) = self.user_session_map.get(&addr) {
-                //     if let Some(session) = self.sessions.get_mut(s_id) {
-                //         let game_finished = session.on_user_con_close(addr);
-                //         if game_finished {
-                //             session.shutdown(&mut self.tx_queue);
-                //         }
-                //     }
-                //     self.user_session_map.remove(&addr);
-                // }
+                if let Some(playback) = self.playbacks.remove(&addr) {
+                    playback.abort();
+                }
             }
         }
     }
 
-    pub fn poll_sub_channels(&mut self) {
-        let mut remove_list = vec![];
-
-        let mut messages = vec![];
-
-        for PlayerChannel { tx, rx, addr } in self.channels.values_mut() {
-            const MAX_READ_COUNT: usize = 128;
-            let mut read_count = 0;
-
-            'read_loop: while read_count < MAX_READ_COUNT {
-                match rx.try_next() {
-                    Ok(Some(message)) => {
-                        messages.push((*addr, message));
-                    }
-                    Ok(None) => {
-                        remove_list.push(*addr);
-                        break 'read_loop;
-                    }
-                    Err(err) => break 'read_loop,
+    fn poll_sub_channels(&mut self) {
+        for (addr, msg) in std::mem::take(&mut self.inbox) {
+            match msg {
+                //the connection joined a session earlier in this batch, the rest is the session's
+                Some(msg) if !self.channels.contains_key(&addr) => self.forward(addr, msg),
+                Some(msg) => self.process_text_msg(addr, msg),
+                None => {
+                    self.channels.remove(&addr);
                 }
-                read_count += 1;
             }
         }
 
-        for (addr, msg) in messages {
-            self.process_text_msg(addr, msg)
-        }
+        //replays stop once their connection closes or joins a session
+        let channels = &self.channels;
+        self.playbacks.retain(|addr, playback| {
+            let watching = channels.contains_key(addr) && !playback.is_finished();
+            if !watching {
+                playback.abort();
+            }
+            watching
+        });
+    }
 
-        for addr in remove_list {
-            self.channels.remove(&addr);
+    /// Hands a message the executor read before `addr` joined a session over to that session.
+    fn forward(&mut self, addr: SocketAddr, msg: RxData) {
+        let session = match self.user_session_map.get(&addr) {
+            Some(Some(s_id)) => self.sessions.get_mut(s_id),
+            _ => None,
+        };
+        match session {
+            Some(session) => session.forward(addr, msg),
+            None => println!(
+                "[session_exec] dropping `{}` from `{}`, its connection is gone",
+                msg.name(),
+                addr
+            ),
         }
     }
 
@@ -444,121 +856,475 @@ impl SessionExecutor {
                 username,
                 session_name,
                 wait_time,
-            } => self.create_session(addr, *wait_time, username, session_name),
+                seed,
+                rules,
+                visibility,
+                password,
+            } => self.create_session(
+                addr,
+                *wait_time,
+                username,
+                session_name,
+                *seed,
+                rules.as_ref(),
+                *visibility,
+                password.as_deref(),
+            ),
+            RxData::DailyChallenge { username } => self.create_daily_challenge(addr, username),
             RxData::CreateUser {
                 session_id,
                 username,
+                password,
             } => {
-                if let Some(s) = self.sessions.get_mut(&session_id) {
-                    let channel = self.channels.remove(&addr).take().unwrap();
-                    match s.create_user(addr, channel, username.to_owned()) {
+                let private = self
+                    .sessions
+                    .get(session_id)
+                    .is_some_and(|s| s.visibility() == SessionVisibility::Private);
+                if private {
+                    println!(
+                        "[session_exec] `{}` tried to join private session `{}` without its code",
+                        addr, session_id
+                    );
+                    self.refuse_user_creation(addr, ErrorCode::Unauthorized, rx_data.name());
+                } else {
+                    self.join_session(addr, session_id, username, password.as_deref(), &rx_data);
+                }
+            }
+            RxData::JoinWithCode {
+                join_code,
+                username,
+                password,
+            } => match self.join_codes.get(&normalize_join_code(join_code)) {
+                Some(session_id) => {
+                    let session_id = *session_id;
+                    self.join_session(addr, &session_id, username, password.as_deref(), &rx_data)
+                }
+                None => self.refuse_user_creation(addr, ErrorCode::UnknownSession, rx_data.name()),
+            },
+            RxData::Login { session_id, token } => {
+                if let Some(s) = self.sessions.get_mut(session_id) {
+                    let Some(channel) = self.channels.remove(&addr) else {
+                        return;
+                    };
+                    match s.login_user(addr, token, channel) {
                         Ok(_) => {
                             self.user_session_map.insert(addr, Some(*s.id()));
                         }
                         Err(channel) => {
                             self.channels.insert(addr, channel);
-                            println!("User creation failed. {} in {}", username, s.id())
+                            println!("Login failed. `{}` in {}", addr, s.id())
                         }
                     }
                 } else {
-                    println!("[session_exec] `{}` requested user creation to invalid session: `{}` as `{}`", addr, session_id, username);
+                    println!(
+                        "[session_exec] `{}` tried to log in to invalid session: `{}`",
+                        addr, session_id
+                    );
+                    let Some(channel) = self.channels.get(&addr) else {
+                        return;
+                    };
+                    send_msg!(
+                        channel.tx,
+                        TxData::LoginResponse {
+                            succeeded: false,
+                            token: None,
+                        }
+                    );
+                    self.send_error(addr, ErrorCode::UnknownSession, rx_data.name());
                 }
             }
-            RxData::Login {
+            RxData::Spectate {
                 session_id,
-                user_id,
+                password,
             } => {
-                if let Some(s) = self.sessions.get_mut(&session_id) {
-                    let channel = self.channels.remove(&addr).take().unwrap();
-                    match s.login_user(addr, *user_id, channel) {
+                if let Some(s) = self.sessions.get_mut(session_id) {
+                    if s.visibility() == SessionVisibility::Private {
+                        if let Some(channel) = self.channels.get(&addr) {
+                            send_msg!(channel.tx, TxData::SpectateResponse { succeeded: false });
+                        }
+                        self.send_error(addr, ErrorCode::Unauthorized, rx_data.name());
+                        return;
+                    }
+                    let Some(channel) = self.channels.remove(&addr) else {
+                        return;
+                    };
+                    match s.add_spectator(channel, password.as_deref()) {
                         Ok(_) => {
                             self.user_session_map.insert(addr, Some(*s.id()));
                         }
                         Err(channel) => {
                             self.channels.insert(addr, channel);
-                            println!("User creation failed. {} in {}", user_id, s.id())
                         }
                     }
+                } else {
+                    let Some(channel) = self.channels.get(&addr) else {
+                        return;
+                    };
+                    send_msg!(channel.tx, TxData::SpectateResponse { succeeded: false });
+                    self.send_error(addr, ErrorCode::UnknownSession, rx_data.name());
+                }
+            }
+            RxData::QuickPlay { username } => {
+                let refused = if self.matchmaker.contains(&addr) {
+                    Some(ErrorCode::AlreadyInSession)
+                } else if username.len() > self.config.session.max_username_len {
+                    Some(ErrorCode::UsernameTooLong)
+                } else {
+                    None
+                };
+                let Some(channel) = self.channels.get(&addr) else {
+                    return;
+                };
+                send_msg!(
+                    channel.tx,
+                    TxData::QuickPlayResponse {
+                        queued: refused.is_none()
+                    }
+                );
+                match refused {
+                    Some(code) => self.send_error(addr, code, rx_data.name()),
+                    None => {
+                        let rating = self.ratings.rating(username);
+                        self.matchmaker.enqueue(addr, username.clone(), rating)
+                    }
+                }
+            }
+            RxData::LeaveQueue => self.matchmaker.remove(&addr),
+            RxData::WatchReplay { replay_id, speed } => {
+                self.watch_replay(addr, replay_id, speed.unwrap_or(1.0))
+            }
+            //players' channels are handed over to their session on login, everything else only
+            //gets here from connections that never logged in
+            _ => {
+                println!(
+                    "[session_exec] `{}` sent `{}` without logging in",
+                    addr,
+                    rx_data.name()
+                );
+                self.send_error(addr, ErrorCode::Unauthorized, rx_data.name());
+            }
+        }
+    }
+
+    fn join_session(
+        &mut self,
+        addr: SocketAddr,
+        session_id: &Uuid,
+        username: &str,
+        password: Option<&str>,
+        request: &RxData,
+    ) {
+        if let Some(s) = self.sessions.get_mut(session_id) {
+            let Some(channel) = self.channels.remove(&addr) else {
+                return;
+            };
+            match s.create_user(addr, channel, username.to_owned(), password) {
+                Ok(_) => {
+                    self.user_session_map.insert(addr, Some(*s.id()));
+                }
+                Err(channel) => {
+                    self.channels.insert(addr, channel);
+                    println!("User creation failed. {} in {}", username, s.id())
+                }
+            }
+        } else {
+            println!(
+                "[session_exec] `{}` requested user creation to invalid session: `{}` as `{}`",
+                addr, session_id, username
+            );
+            self.refuse_user_creation(addr, ErrorCode::UnknownSession, request.name());
+        }
+    }
+
+    /// When the matchmaker wants to run next, as long as there's room for another session.
+    fn matchmaking_deadline(&self) -> Option<Instant> {
+        if self.sessions.len() >= self.config.session_exec.max_sessions {
+            return None;
+        }
+        self.matchmaker.next_deadline(&self.config.matchmaking)
+    }
+
+    /// Starts a session for every group of queued players that's ready to play.
+    fn run_matchmaking(&mut self) {
+        let channels = &self.channels;
+        //connections that closed or joined a session some other way
+        self.matchmaker.retain(|addr| channels.contains_key(addr));
+
+        while self.sessions.len() < self.config.session_exec.max_sessions {
+            let players = match self
+                .matchmaker
+                .next_match(&self.config.matchmaking, Instant::now())
+            {
+                Some(players) => players,
+                None => break,
+            };
+
+            let seed = rand::random();
+            let join_code = self.new_join_code();
+            let mut session = self
+                .new_session("Quick play".to_owned(), self.config.session, seed)
+                .with_access(SessionVisibility::Unlisted, join_code.clone(), None)
+                .without_lobby();
+            let id = *session.id();
+            let mut joined = 0;
+            for player in players {
+                if !self.channels.contains_key(&player.addr) {
+                    continue;
+                }
+                //they were told they're queued, so they hear why they aren't playing instead
+                if let Some(code) = session.refusal(&player.addr, &player.username, None) {
+                    self.send_error(player.addr, code, "QuickPlay");
+                    continue;
+                }
+                let channel = self.channels.remove(&player.addr).unwrap();
+                let tx = channel.tx.clone();
+                match session.create_user(player.addr, channel, player.username, None) {
+                    Ok(_) => {
+                        send_msg!(
+                            tx,
+                            TxData::MatchFound {
+                                session_id: id,
+                                seed
+                            }
+                        );
+                        self.user_session_map.insert(player.addr, Some(id));
+                        joined += 1;
+                    }
+                    Err(channel) => {
+                        self.channels.insert(player.addr, channel);
+                    }
+                }
+            }
+            if joined == 0 {
+                continue;
+            }
+            println!("[session_exec] quick play session `{}` is starting", id);
+
+            session.start_waiting_game();
+            self.join_codes.insert(join_code, id);
+            self.sessions.insert(id, session);
+        }
+    }
+
+    /// Streams a stored replay to `addr` without holding up the other sessions, in place of the
+    /// one it was watching.
+    fn watch_replay(&mut self, addr: SocketAddr, replay_id: &Uuid, speed: f64) {
+        let Some(channel) = self.channels.get(&addr) else {
+            return;
+        };
+        let tx = channel.tx.clone();
+        if let Some(playback) = self.playbacks.remove(&addr) {
+            playback.abort();
+        }
+        self.playbacks.retain(|_, playback| !playback.is_finished());
+
+        let refused = if !(replay::MIN_SPEED..=replay::MAX_SPEED).contains(&speed) {
+            Some(ErrorCode::InvalidRules)
+        } else if self.config.server.replay_dir.is_empty() {
+            Some(ErrorCode::UnknownReplay)
+        } else if self.playbacks.len() >= self.config.session_exec.max_playbacks {
+            Some(ErrorCode::ServerFull)
+        } else {
+            None
+        };
+        if let Some(code) = refused {
+            send_msg!(tx, TxData::WatchReplayResponse { succeeded: false });
+            self.send_error(addr, code, "WatchReplay");
+            return;
+        }
+
+        let (dir, replay_id) = (self.config.server.replay_dir.clone(), *replay_id);
+        let playback = tokio::task::spawn_local(async move {
+            let replay = tokio::task::spawn_blocking(move || Replay::load(&dir, &replay_id)).await;
+            match replay {
+                Ok(Ok(replay)) => {
+                    send_msg!(tx, TxData::WatchReplayResponse { succeeded: true });
+                    replay.play(tx, speed).await;
                 }
-                //TODO: send back response if login doesnt succeed
-            }
-            _ => {} //TODO: Handle all the below cases in `Session`
-                    // RxData::ValidationData { session_id, .. }
-                    // | RxData::LaunchGame { session_id, .. }
-                    // | RxData::Map { session_id, .. } => {
-                    //     if let Some(s) = self.sessions.get_mut(&session_id) {
-                    //         s.on_recv(&mut self.tx_queue, addr, rx_data);
-                    //     } else {
-                    //         println!(
-                    //             "[session_exec] `{}` sent data to invalid session: `{}`",
-                    //             addr, session_id
-                    //         );
-                    //     }
-                    // }
-                    // RxData::GameOver {
-                    //     session_id,
-                    //     user_id,
-                    //     ..
-                    // } => {
-                    //     if let Some(s) = self.sessions.get_mut(&session_id) {
-                    //         if let Ok(active_players) = s.user_game_over(&mut self.tx_queue, *user_id, addr)
-                    //         {
-                    //             self.user_session_map.remove(&addr);
-                    //             self.session_hosts.remove(&addr);
-                    //             if active_players <= 0 {
-                    //                 println!("[session_exec] 0 active players, closing session...");
-                    //                 s.shutdown(&mut self.tx_queue);
-                    //             }
-                    //         }
-                    //     } else {
-                    //         println!(
-                    //             "[session_exec] `{}` sent game over message to invalid session: `{}`",
-                    //             addr, session_id
-                    //         );
-                    //     }
-                    // }
+                Ok(Err(err)) => {
+                    println!(
+                        "[session_exec] `{}` can't watch replay `{}`: {}",
+                        addr, replay_id, err
+                    );
+                    send_msg!(tx, TxData::WatchReplayResponse { succeeded: false });
+                    send_msg!(tx, TxData::error(ErrorCode::UnknownReplay, "WatchReplay"));
+                }
+                Err(err) => println!(
+                    "[session_exec] loading replay `{}` failed: {}",
+                    replay_id, err
+                ),
+            }
+        });
+        self.playbacks.insert(addr, playback);
+    }
+
+    /// A session made with the server's defaults, recording replays if there's somewhere to
+    /// save them.
+    fn new_session(&self, name: String, config: SessionConfig, seed: u32) -> Session {
+        Session::new(name, config, seed, self.tokens)
+            .with_replays(!self.config.server.replay_dir.is_empty())
+    }
+
+    /// A join code no other session has.
+    fn new_join_code(&self) -> String {
+        loop {
+            let code: String = (0..JOIN_CODE_LEN)
+                .map(|_| JOIN_CODE_CHARS[rand::random::<usize>() % JOIN_CODE_CHARS.len()] as char)
+                .collect();
+            if !self.join_codes.contains_key(&code) {
+                return code;
+            }
         }
     }
 
+    /// Tells `addr` why its `request` was refused, as long as it hasn't joined a session.
+    fn send_error(&self, addr: SocketAddr, code: ErrorCode, request: &str) {
+        if let Some(channel) = self.channels.get(&addr) {
+            send_msg!(channel.tx, TxData::error(code, request));
+        }
+    }
+
+    fn send_query_response(&self, addr: SocketAddr, query_res: QueryResponseType) {
+        if let Some(channel) = self.channels.get(&addr) {
+            send_msg!(channel.tx, TxData::QueryResponse { query_res });
+        }
+    }
+
+    /// `send_error` preceded by the failed `UserCreationResponse` clients wait for.
+    fn refuse_user_creation(&self, addr: SocketAddr, code: ErrorCode, request: &str) {
+        if let Some(channel) = self.channels.get(&addr) {
+            send_msg!(
+                channel.tx,
+                TxData::UserCreationResponse {
+                    creation_succeeded: false,
+                    user_id: None,
+                    token: None,
+                }
+            );
+        }
+        self.send_error(addr, code, request);
+    }
+
+    /// `send_error` preceded by the failed `SessionCreationResponse` clients wait for.
+    fn refuse_session_creation(&self, addr: SocketAddr, code: ErrorCode, request: &str) {
+        if let Some(channel) = self.channels.get(&addr) {
+            send_msg!(
+                channel.tx,
+                TxData::SessionCreationResponse {
+                    creation_succeeded: false,
+                    session_id: None,
+                    seed: None,
+                    join_code: None,
+                }
+            );
+        }
+        self.send_error(addr, code, request);
+    }
+
     fn handle_query(&mut self, addr: SocketAddr, query: &QueryType) {
         match query {
             QueryType::LeaderBoard { session_id } => {
-                if self.sessions.contains_key(&session_id) {
-                    send_msg!(
-                        self.channels.get_mut(&addr).unwrap().tx,
-                        TxData::QueryResponse {
-                            query_res: QueryResponseType::LeaderBoard {
-                                session_id: *session_id,
-                                scores: self.sessions.get(&session_id).unwrap().get_leaderboard(),
-                            },
-                        }
+                if let Some(s) = self.sessions.get(session_id) {
+                    self.send_query_response(
+                        addr,
+                        QueryResponseType::LeaderBoard {
+                            session_id: *session_id,
+                            scores: s.get_leaderboard(),
+                            flagged: s.get_flagged(),
+                        },
                     );
                 } else {
                     println!(
                         "[session_exec] Invalid session `{}` queried for leaderboard by `{}`.",
                         session_id, addr
-                    )
+                    );
+                    self.send_error(addr, ErrorCode::UnknownSession, "Query");
                 }
             }
             QueryType::SessionStatus { session_id } => {
                 if let Some(s) = self.sessions.get(session_id) {
                     let (status, duration) = s.get_status();
-                    send_msg!(
-                        self.channels.get_mut(&addr).unwrap().tx,
-                        TxData::QueryResponse {
-                            query_res: QueryResponseType::SessionStatus {
-                                status,
-                                time: duration,
-                            },
-                        }
+                    self.send_query_response(
+                        addr,
+                        QueryResponseType::SessionStatus {
+                            status,
+                            time: duration,
+                            seed: s.seed(),
+                        },
                     );
+                } else {
+                    self.send_error(addr, ErrorCode::UnknownSession, "Query");
                 }
             }
 
+            QueryType::DailyLeaderBoard { date } => {
+                let Some(daily_keys) = &self.daily_keys else {
+                    self.send_error(addr, ErrorCode::DailyChallengeDisabled, "Query");
+                    return;
+                };
+                let day = match date {
+                    Some(date) => daily_challenge::parse_date(date),
+                    None => Some(daily_challenge::today()),
+                }
+                //the maps of the days to come stay a secret until then
+                .filter(|day| *day <= daily_challenge::today());
+                if let Some(day) = day {
+                    self.send_query_response(
+                        addr,
+                        QueryResponseType::DailyLeaderBoard {
+                            date: daily_challenge::format_date(day),
+                            seed: daily_challenge::seed(day, daily_keys),
+                            scores: self.daily_leaderboards.scores(day),
+                        },
+                    );
+                } else {
+                    println!(
+                        "[session_exec] Invalid date `{}` queried for daily leaderboard by `{}`.",
+                        date.as_deref().unwrap_or_default(),
+                        addr
+                    );
+                    self.send_error(addr, ErrorCode::MalformedMessage, "Query");
+                }
+            }
+
+            QueryType::TopScores { limit } => {
+                let scores = self.matches.top_scores(query_limit(*limit));
+                self.send_query_response(addr, QueryResponseType::TopScores { scores });
+            }
+            QueryType::PlayerHistory { username, limit } => {
+                let matches = self.matches.history(username, query_limit(*limit));
+                self.send_query_response(
+                    addr,
+                    QueryResponseType::PlayerHistory {
+                        username: username.clone(),
+                        matches,
+                    },
+                );
+            }
+            QueryType::RecentMatches { limit } => {
+                let matches = self.matches.recent(query_limit(*limit));
+                self.send_query_response(addr, QueryResponseType::RecentMatches { matches });
+            }
+            QueryType::Ratings { limit } => {
+                let players = self.ratings.ladder(query_limit(*limit));
+                self.send_query_response(addr, QueryResponseType::Ratings { players });
+            }
+            QueryType::RatingHistory { username, limit } => {
+                self.send_query_response(
+                    addr,
+                    QueryResponseType::RatingHistory {
+                        username: username.clone(),
+                        rating: self.ratings.rating(username),
+                        history: self.ratings.history(username, query_limit(*limit)),
+                    },
+                );
+            }
+
             QueryType::Sessions => {
                 let sessions = self
                     .sessions
                     .keys()
+                    .filter(|k| self.sessions[k].visibility() == SessionVisibility::Public)
                     .map(|k| {
                         let session = self.sessions.get(k).unwrap();
                         let status = match session.status() {
@@ -574,147 +1340,284 @@ impl SessionExecutor {
                             *k,
                             session.name().to_owned(),
                             status,
-                            session.get_usernames(),
+                            session
+                                .get_usernames()
+                                .into_iter()
+                                .map(|username| {
+                                    let rating = self.ratings.rating(&username);
+                                    (username, rating)
+                                })
+                                .collect(),
                         )
                     })
                     .collect();
 
-                send_msg!(
-                    self.channels.get_mut(&addr).unwrap().tx,
-                    TxData::QueryResponse {
-                        query_res: QueryResponseType::Sessions { sessions }
-                    }
-                );
+                self.send_query_response(addr, QueryResponseType::Sessions { sessions });
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)] //one per field of `RxData::CreateSession`
     fn create_session(
         &mut self,
         addr: SocketAddr,
         wait_time: u64,
         username: &str,
         session_name: &str,
+        seed: Option<u32>,
+        rules: Option<&SessionRules>,
+        visibility: SessionVisibility,
+        password: Option<&str>,
     ) {
-        if let Some(s) = self.user_session_map.get(&addr).unwrap() {
+        if let Some(Some(s)) = self.user_session_map.get(&addr) {
             println!("[session_exec] `{}` requested session creation as `{}` but was already in another sesssion: `{}`", addr, username, s);
+            self.refuse_session_creation(addr, ErrorCode::AlreadyInSession, "CreateSession");
             return;
         }
-        if self.sessions.len() < self.config.session_exec.max_sessions
-            && !self.session_hosts.contains_key(&addr)
-            && username.len() <= self.config.session.max_username_len
-            && (self
-                .sessions
-                .iter()
-                .filter(|(_, s)| {
-                    if let SessionStatus::Waiting { .. } = s.status() {
-                        true
-                    } else {
-                        false
-                    }
-                })
-                .count()
-                == 0
-                || self.config.session_exec.allow_multiple_inactive_sessions)
+
+        let waiting_sessions = self
+            .sessions
+            .values()
+            .filter(|s| matches!(s.status(), SessionStatus::Waiting { .. }))
+            .count();
+        let refused = if self.session_hosts.contains_key(&addr) {
+            Some(ErrorCode::AlreadyInSession)
+        } else if username.len() > self.config.session.max_username_len {
+            Some(ErrorCode::UsernameTooLong)
+        } else if self.sessions.len() >= self.config.session_exec.max_sessions
+            || (waiting_sessions > 0 && !self.config.session_exec.allow_multiple_inactive_sessions)
+        {
+            Some(ErrorCode::ServerFull)
+        } else {
+            None
+        };
+        if let Some(code) = refused {
+            self.refuse_session_creation(addr, code, "CreateSession");
+            return;
+        }
+
+        let config = match rules.map_or(Ok(self.config.session), |rules| {
+            rules.apply(self.config.session)
+        }) {
+            Ok(config) => config,
+            Err(err) => {
+                println!(
+                    "[session_exec] `{}` requested a session with invalid rules: {}",
+                    addr, err
+                );
+                self.refuse_session_creation(addr, ErrorCode::InvalidRules, "CreateSession");
+                return;
+            }
+        };
+
+        let Some(channel) = self.channels.remove(&addr) else {
+            return;
+        };
+        let seed = seed.unwrap_or_else(rand::random);
+        self.index_saved_replays();
+        let ghosts = rules.map_or(vec![], |rules| {
+            self.find_ghosts(&rules.ghosts, seed, &config)
+        });
+        let join_code = self.new_join_code();
+        match self
+            .new_session(session_name.to_owned(), config, seed)
+            .with_access(visibility, join_code.clone(), password)
+            .with_ghosts(ghosts)
+            .with_host(channel, username.to_owned(), addr, wait_time)
         {
-            let channel = self.channels.remove(&addr).unwrap();
-            match Session::new(session_name.to_owned(), self.config.session).with_host(
+            Ok(session) => {
+                let id = *session.id();
+                self.join_codes.insert(join_code, id);
+                self.set_host_addr(id, Some(addr));
+                self.sessions.insert(id, session);
+                self.user_session_map.insert(addr, Some(id));
+            }
+            Err(channel) => {
+                self.channels.insert(addr, channel);
+                self.refuse_session_creation(addr, ErrorCode::UsernameTooLong, "CreateSession");
+            }
+        }
+    }
+
+    /// Adds the runs of stored matches to the ghost index once their replay is saved, so a
+    /// ghost is never looked up in a replay that isn't there.
+    fn index_saved_replays(&mut self) {
+        while let Ok(Some((replay, saved))) = self.saved_replays.try_next() {
+            let Some(record) = self.unsaved_ghosts.remove(&replay) else {
+                continue;
+            };
+            if saved {
+                self.ghosts.add(&record);
+            }
+        }
+    }
+
+    /// The recorded runs on the map of `seed` that `choices` ask for, the ones that don't exist
+    /// yet are left out.
+    fn find_ghosts(
+        &self,
+        choices: &[GhostChoice],
+        seed: u32,
+        config: &SessionConfig,
+    ) -> Vec<Ghost> {
+        let dir = &self.config.server.replay_dir;
+        if dir.is_empty() {
+            return vec![];
+        }
+        choices
+            .iter()
+            .filter_map(|choice| {
+                let username = match choice {
+                    GhostChoice::Best => None,
+                    GhostChoice::PersonalBest { username } => Some(username.as_str()),
+                };
+                let (id, username) = self.ghosts.best(seed, &config.physics, username)?;
+                replay::load_ghost(dir, &id, username)
+                    .map_err(|err| {
+                        println!(
+                            "[session_exec] failed to load the ghost of `{}` from replay `{}`: {}",
+                            username, id, err
+                        )
+                    })
+                    .ok()
+            })
+            .inspect(|ghost| {
+                println!(
+                    "[session_exec] found a ghost of `{}` scoring {} on seed {}",
+                    ghost.username, ghost.score, seed
+                )
+            })
+            .collect()
+    }
+
+    /// Starts a single player session on today's daily challenge map, the score it ends with
+    /// goes on the day's leaderboard.
+    fn create_daily_challenge(&mut self, addr: SocketAddr, username: &str) {
+        let Some(daily_keys) = self.daily_keys else {
+            self.refuse_session_creation(addr, ErrorCode::DailyChallengeDisabled, "DailyChallenge");
+            return;
+        };
+        if let Some(Some(s)) = self.user_session_map.get(&addr) {
+            println!("[session_exec] `{}` requested the daily challenge as `{}` but was already in another sesssion: `{}`", addr, username, s);
+            self.refuse_session_creation(addr, ErrorCode::AlreadyInSession, "DailyChallenge");
+            return;
+        }
+        if username.len() > self.config.session.max_username_len {
+            self.refuse_session_creation(addr, ErrorCode::UsernameTooLong, "DailyChallenge");
+            return;
+        }
+        if self.sessions.len() >= self.config.session_exec.max_sessions {
+            self.refuse_session_creation(addr, ErrorCode::ServerFull, "DailyChallenge");
+            return;
+        }
+
+        let day = daily_challenge::today();
+        let Some(channel) = self.channels.remove(&addr) else {
+            return;
+        };
+        let config = SessionConfig {
+            max_users: 1,
+            rematch_window: 0, //one attempt a day
+            ..self.config.session
+        };
+        match self
+            .new_session(
+                format!("Daily challenge {}", daily_challenge::format_date(day)),
+                config,
+                daily_challenge::seed(day, &daily_keys),
+            )
+            .with_host(
                 channel,
                 username.to_owned(),
                 addr,
-                wait_time,
+                daily_challenge::WAIT_TIME,
             ) {
-                Ok(session) => {
-                    let id = *session.id();
-                    self.session_hosts.insert(addr, id);
-                    self.sessions.insert(id, session);
-                    self.user_session_map.insert(addr, Some(id));
-                }
-                Err(channel) => {
-                    self.channels.insert(addr, channel);
-                }
+            Ok(session) => {
+                let id = *session.id();
+                self.sessions.insert(id, session);
+                self.user_session_map.insert(addr, Some(id));
+                self.daily_sessions.insert(id, day);
+            }
+            Err(channel) => {
+                self.channels.insert(addr, channel);
+                self.refuse_session_creation(addr, ErrorCode::UsernameTooLong, "DailyChallenge");
             }
-        } else {
-            send_msg!(
-                self.channels.get(&addr).unwrap().tx,
-                TxData::SessionCreationResponse {
-                    creation_succeeded: false,
-                    session_id: None,
-                }
-            );
         }
     }
 
-    // fn send_tx_queue(&mut self) {
-    //     let senders = self.peer_map.clone();
-    //     self.tx_queue.queue.iter().for_each(move |(addr, msg)| {
-    //         #[cfg(debug_assertions)]
-    //         let serialized_msg = serde_json::to_string_pretty(&msg).unwrap();
-    //         #[cfg(not(debug_assertions))]
-    //         let serialized_msg = serde_json::to_string(&msg).unwrap();
-    //
-    //         let mut senders = senders.lock().unwrap();
-    //         if let Some(sender) = senders.get_mut(addr) {
-    //             if let Err(e) = sender.1.unbounded_send(WsMessage::Text(serialized_msg)) {
-    //                 println!(
-    //                     "[session_exec] Error sending message to `{}`: `{}`",
-    //                     addr, e
-    //                 )
-    //             }
-    //         } else {
-    //             println!(
-    //                 "[session_exec] Requested transmission to unknown address: `{}`",
-    //                 addr
-    //             )
-    //         }
-    //     });
-    //
-    //     self.tx_queue.clear_queue();
-    // }
-
-    // fn close_abandoned_cons(&mut self) {
-    //     if self.tx_queue.closable.is_empty() {
-    //         return;
-    //     };
-    //     let mut senders = self.peer_map.lock().unwrap();
-    //     self.tx_queue.closable.iter().for_each(|addr| {
-    //         if let Some(sender) = senders.get_mut(addr) {
-    //             sender.1.close_channel();
-    //             sender.0.close();
-    //             senders.remove(addr);
-    //             println!("[session_exec] closed connection from `{}`", addr);
-    //         } else {
-    //             println!(
-    //                 "[session_exec] requested closing unknown address: `{}`",
-    //                 addr
-    //             );
-    //         }
-    //     });
-    //     self.tx_queue.clear_closable();
-    // }
-
     fn close_session(&mut self, s_id: &Uuid) {
         if let Some(session) = self.sessions.get(s_id) {
-            if let Some(host_addr) = session.get_host_addr() {
-                self.session_hosts.remove(&host_addr);
-            }
-            self.sessions.remove(&s_id);
+            self.join_codes.remove(session.join_code());
+            self.set_host_addr(*s_id, None);
+            self.sessions.remove(s_id);
+            self.daily_sessions.remove(s_id);
             println!("[session exec] closed session `{}`", s_id);
         }
     }
 
-    #[inline(always)]
-    pub fn run(&mut self) {
+    fn set_host_addr(&mut self, s_id: Uuid, addr: Option<SocketAddr>) {
+        if let Some(old) = self.host_addrs.remove(&s_id) {
+            self.session_hosts.remove(&old);
+        }
+        if let Some(addr) = addr {
+            self.session_hosts.insert(addr, s_id);
+            self.host_addrs.insert(s_id, addr);
+        }
+    }
+
+    fn run_sessions(&mut self) {
+        let mut host_changes = vec![];
         for (s_id, s) in &mut self.sessions {
             let game_finished = s.game_loop();
+            for replay in s.take_replays() {
+                let dir = self.config.server.replay_dir.clone();
+                let saved = self.saved_replays_tx.clone();
+                tokio::task::spawn_blocking(move || {
+                    let result = replay.save(&dir);
+                    if let Err(err) = &result {
+                        println!(
+                            "[session_exec] failed to save replay `{}`: {}",
+                            replay.id, err
+                        );
+                    }
+                    let _ = saved.unbounded_send((replay.id, result.is_ok()));
+                });
+            }
+            for record in s.take_matches() {
+                //a match that isn't stored wouldn't be rated or indexed again on restart
+                match self.matches.record(record.clone()) {
+                    Ok(()) => {
+                        self.ratings.rate(&record);
+                        if let Some(replay) = record.replay {
+                            self.unsaved_ghosts.insert(replay, record);
+                        }
+                    }
+                    Err(err) => println!(
+                        "[session_exec] failed to store a match of `{}`: {}",
+                        s_id, err
+                    ),
+                }
+            }
+            //hosts change on migration, on transfer and when they log in on a new connection
+            let host = s.get_host_addr();
+            if host != self.host_addrs.get(s_id).copied() {
+                host_changes.push((*s_id, host));
+            }
+            if let Some(day) = self.daily_sessions.get(s_id) {
+                for (username, score) in s.finished_players() {
+                    self.daily_leaderboards.record(*day, &username, score);
+                }
+            }
             if game_finished {
-                s.shutdown(&mut self.tx_queue);
+                s.shutdown();
                 self.closable_sessions.push(*s_id);
             }
         }
-        // self.send_tx_queue();
-        // self.close_abandoned_cons();
-        if self.closable_sessions.len() <= 0 {
+        for (s_id, host) in host_changes {
+            self.set_host_addr(s_id, host);
+        }
+        self.index_saved_replays();
+        if self.closable_sessions.is_empty() {
             return;
         }
 
@@ -724,3 +1627,190 @@ impl SessionExecutor {
         self.closable_sessions.clear();
     }
 }
+
+/// Polls the channels of connections that haven't joined a session yet as well as every
+/// session's player channels, resolving once any of them produced a message or closed.
+fn poll_receivers(
+    channels: &mut FxHashMap<SocketAddr, PlayerChannel>,
+    sessions: &mut FxHashMap<Uuid, Session>,
+    inbox: &mut Vec<(SocketAddr, Option<RxData>)>,
+    cx: &mut Context<'_>,
+) -> Poll<()> {
+    let mut ready = false;
+
+    for PlayerChannel { rx, addr, .. } in channels.values_mut() {
+        let mut read_count = 0;
+        while read_count < MAX_READ_COUNT {
+            match rx.poll_next_unpin(cx) {
+                Poll::Ready(msg) => {
+                    let closed = msg.is_none();
+                    inbox.push((*addr, msg));
+                    ready = true;
+                    if closed {
+                        break;
+                    }
+                }
+                Poll::Pending => break,
+            }
+            read_count += 1;
+        }
+    }
+
+    for session in sessions.values_mut() {
+        ready |= session.poll_recv(cx).is_ready();
+    }
+
+    if ready {
+        Poll::Ready(())
+    } else {
+        Poll::Pending
+    }
+}
+
+#[test]
+fn messages_read_before_joining_go_to_the_session() {
+    use crate::storage::MemoryStore;
+    use futures_channel::mpsc::unbounded;
+
+    let (_, rx) = mpsc::channel(1);
+    let config = ConfigOptions::default();
+    let mut exec = SessionExecutor::new_with_channel(rx, config, Box::<MemoryStore>::default());
+    let mut connect = |port: u16| {
+        let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+        let (tx, outbox) = unbounded();
+        let (_, rx) = unbounded();
+        exec.user_session_map.insert(addr, None);
+        exec.channels.insert(addr, PlayerChannel { tx, rx, addr });
+        (addr, outbox)
+    };
+    let (host, _) = connect(1);
+    let (addr, mut outbox) = connect(2);
+    let msg = |json: &str| Some(serde_json::from_str(json).unwrap());
+
+    exec.inbox.push((
+        host,
+        msg(r#"{"type":"CreateSession","username":"a","sessionName":"lunch","waitTime":60}"#),
+    ));
+    exec.poll_sub_channels();
+    let session_id = *exec.sessions.keys().next().unwrap();
+
+    //both read in the same batch, quick play arrives after the channel moved to the session
+    let create_user = format!(
+        r#"{{"type":"CreateUser","sessionId":"{}","username":"b"}}"#,
+        session_id
+    );
+    exec.inbox.push((addr, msg(&create_user)));
+    exec.inbox
+        .push((addr, msg(r#"{"type":"QuickPlay","username":"b"}"#)));
+    exec.poll_sub_channels();
+    exec.run_sessions();
+
+    let sent: Vec<TxData> = std::iter::from_fn(|| outbox.try_next().ok().flatten()).collect();
+    assert!(matches!(
+        sent[0],
+        TxData::UserCreationResponse {
+            creation_succeeded: true,
+            ..
+        }
+    ));
+    assert!(sent.iter().any(|msg| matches!(
+        msg,
+        TxData::Error {
+            code: ErrorCode::AlreadyInSession,
+            ..
+        }
+    )));
+}
+
+#[test]
+fn quick_play_never_leaves_players_waiting_on_a_match_they_arent_in() {
+    use crate::storage::MemoryStore;
+    use futures_channel::mpsc::{unbounded, UnboundedReceiver};
+
+    let mut config = ConfigOptions::default();
+    config.matchmaking.target_size = 2;
+    config.matchmaking.min_size = 2;
+    let (_, rx) = mpsc::channel(1);
+    let mut exec = SessionExecutor::new_with_channel(rx, config, Box::<MemoryStore>::default());
+    let queue = |exec: &mut SessionExecutor, port: u16, username: &str| {
+        let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+        let (tx, outbox) = unbounded();
+        let (_, rx) = unbounded();
+        exec.user_session_map.insert(addr, None);
+        exec.channels.insert(addr, PlayerChannel { tx, rx, addr });
+        let quick_play = format!(r#"{{"type":"QuickPlay","username":"{}"}}"#, username);
+        exec.inbox
+            .push((addr, Some(serde_json::from_str(&quick_play).unwrap())));
+        exec.poll_sub_channels();
+        exec.run_matchmaking();
+        outbox
+    };
+    let sent = |outbox: &mut UnboundedReceiver<TxData>| -> Vec<TxData> {
+        std::iter::from_fn(|| outbox.try_next().ok().flatten()).collect()
+    };
+    let matched = |sent: &[TxData]| {
+        sent.iter()
+            .any(|msg| matches!(msg, TxData::MatchFound { .. }))
+    };
+
+    let mut a = queue(&mut exec, 1, "a");
+    let mut other_a = queue(&mut exec, 2, "a");
+    assert!(
+        exec.sessions.is_empty(),
+        "usernames are unique within a match"
+    );
+    let mut b = queue(&mut exec, 3, "b");
+    assert_eq!(exec.sessions.len(), 1);
+    assert!(matched(&sent(&mut a)) && matched(&sent(&mut b)));
+    assert!(!matched(&sent(&mut other_a)));
+    let mut c = queue(&mut exec, 4, "c");
+    assert_eq!(exec.sessions.len(), 2);
+    assert!(matched(&sent(&mut other_a)) && matched(&sent(&mut c)));
+
+    //nobody fits in the session, so there's no session and both hear why
+    exec.config.session.max_users = 0;
+    let mut d = queue(&mut exec, 5, "d");
+    let mut e = queue(&mut exec, 6, "e");
+    assert_eq!(exec.sessions.len(), 2);
+    for sent in [sent(&mut d), sent(&mut e)] {
+        assert!(sent.iter().any(|msg| matches!(
+            msg,
+            TxData::Error {
+                code: ErrorCode::SessionFull,
+                ..
+            }
+        )));
+    }
+}
+
+#[test]
+fn daily_maps_survive_restarts_and_need_a_key() {
+    use crate::storage::MemoryStore;
+    use futures_channel::mpsc::unbounded;
+
+    let daily_seed = |daily_key: &str| {
+        let mut config = ConfigOptions::default();
+        config.server.daily_key = daily_key.to_owned();
+        let (_, rx) = mpsc::channel(1);
+        let mut exec = SessionExecutor::new_with_channel(rx, config, Box::<MemoryStore>::default());
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let (tx, mut outbox) = unbounded();
+        let (_, rx) = unbounded();
+        exec.channels.insert(addr, PlayerChannel { tx, rx, addr });
+        exec.handle_query(addr, &QueryType::DailyLeaderBoard { date: None });
+        match outbox.try_next().unwrap().unwrap() {
+            TxData::QueryResponse {
+                query_res: QueryResponseType::DailyLeaderBoard { seed, .. },
+            } => Some(seed),
+            TxData::Error {
+                code: ErrorCode::DailyChallengeDisabled,
+                ..
+            } => None,
+            _ => panic!("unexpected response"),
+        }
+    };
+
+    assert_eq!(daily_seed("secret"), daily_seed("secret"));
+    assert!(daily_seed("secret").is_some());
+    assert_eq!(daily_seed(""), None);
+}
@@ -0,0 +1,356 @@
+use crate::math;
+use crate::obstacles::{obstacle_size, Obstacle};
+use crate::validator::SessionPhysicsConfig;
+
+/// Distance between the start of the track and the dino's left edge, the client draws the dino
+/// this far into the screen.
+pub const DINO_X_OFFSET: f64 = 1.5;
+
+/// Trimmed from every side of the dino's and the obstacles' hitboxes, the sprites don't fill
+/// their bounding boxes and a pixel perfect check would feel unfair.
+pub const HITBOX_INSET: f64 = 0.1;
+
+/// How far behind the current game time collisions are checked, so jumps from players with
+/// some latency still count before they are declared dead.
+pub const INPUT_DELAY: f64 = 0.25;
+
+/// Run and duck sprite sizes, the dino is `DINO_RUN` unless it's ducking on the ground.
+const DINO_RUN: Obstacle = Obstacle::DinoRun1;
+const DINO_DUCK: Obstacle = Obstacle::DinoDuck1;
+
+/// x position of every dino at `t` seconds into the game, everyone runs at the same speed.
+pub fn x_at(t: f64, physics: &SessionPhysicsConfig) -> f64 {
+    let (u, a) = (physics.initial_vel as f64, physics.x_acc as f64);
+    u * t + 0.5 * a * t * t
+}
+
+/// Inverse of `x_at`.
+pub fn time_at_x(x: f64, physics: &SessionPhysicsConfig) -> f64 {
+    let (u, a) = (physics.initial_vel as f64, physics.x_acc as f64);
+    if a == 0.0 {
+        return x / u;
+    }
+    // 1/2at^2 + ut - x = 0
+    (-u + (u * u + 2.0 * a * x).sqrt()) / a
+}
+
+fn jump_duration(physics: &SessionPhysicsConfig) -> f64 {
+    math::jump_time(physics.jump_vel, physics.gravity) as f64
+}
+
+/// Replays a single dino's jump and duck inputs against the map.
+///
+/// Every time is in seconds since the game started. Inputs can't be placed before the point up
+/// to which collisions were already checked, history is never rewritten.
+#[derive(Default)]
+pub struct DinoSim {
+    jumps: Vec<f64>, //start times, a jump is only accepted on the ground so they never overlap
+    ducks: Vec<(f64, f64)>, //[start, end), the last one is open ended while the key is held
+    checked_until: f64,
+    next_obstacle: usize,
+    dead_at: Option<f64>,
+}
+
+struct Hitbox {
+    x: (f64, f64),
+    y: (f64, f64),
+}
+
+impl DinoSim {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Game time up to which collisions have been checked.
+    pub fn checked_until(&self) -> f64 {
+        self.checked_until
+    }
+
+    /// Returns `false` if the jump was ignored because the dino was still in the air.
+    pub fn jump(&mut self, t: f64, physics: &SessionPhysicsConfig) -> bool {
+        let t = t.max(self.checked_until);
+        if let Some(last) = self.jumps.last() {
+            if t < last + jump_duration(physics) {
+                return false;
+            }
+        }
+        self.jumps.push(t);
+        true
+    }
+
+    pub fn duck_start(&mut self, t: f64) {
+        let t = t.max(self.checked_until);
+        match self.ducks.last() {
+            Some((_, end)) if end.is_infinite() => (),
+            Some((_, end)) if t < *end => (),
+            _ => self.ducks.push((t, f64::INFINITY)),
+        }
+    }
+
+    pub fn duck_end(&mut self, t: f64) {
+        let t = t.max(self.checked_until);
+        if let Some((start, end)) = self.ducks.last_mut() {
+            if end.is_infinite() {
+                *end = t.max(*start);
+            }
+        }
+    }
+
+    fn jump_start_at(&self, t: f64, physics: &SessionPhysicsConfig) -> Option<f64> {
+        let duration = jump_duration(physics);
+        self.jumps
+            .iter()
+            .rev()
+            .find(|start| **start <= t)
+            .filter(|start| t < **start + duration)
+            .copied()
+    }
+
+    fn is_ducking_at(&self, t: f64) -> bool {
+        self.ducks
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= t)
+            .is_some_and(|(_, end)| t < *end)
+    }
+
+    fn height_at(jump_start: Option<f64>, t: f64, physics: &SessionPhysicsConfig) -> f64 {
+        match jump_start {
+            Some(start) => {
+                (math::jump_height_at_t((t - start) as f32, physics.jump_vel, physics.gravity)
+                    as f64)
+                    .max(0.0)
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Checks collisions between the last checked time and `until`.
+    ///
+    /// `map` must contain every obstacle the dino can reach by then. Returns the time of the
+    /// first collision, after which the dino stays dead.
+    pub fn advance(
+        &mut self,
+        until: f64,
+        map: &[((f64, f64), Vec<Obstacle>)],
+        physics: &SessionPhysicsConfig,
+    ) -> Option<f64> {
+        if self.dead_at.is_some() || until <= self.checked_until {
+            return self.dead_at;
+        }
+
+        let widest = obstacle_size(&DINO_RUN).0.max(obstacle_size(&DINO_DUCK).0) as f64;
+
+        //windows of neighbouring obstacles can overlap, so every one entered before `until` is
+        //checked even while an earlier one hasn't been passed yet
+        let mut first_unpassed = None;
+        let mut hit: Option<f64> = None;
+        let mut idx = self.next_obstacle;
+        'map: while let Some(((x, y), obstacles)) = map.get(idx) {
+            if !x.is_finite() || !y.is_finite() {
+                //the client skips these as well
+                idx += 1;
+                continue;
+            }
+
+            let mut x_offset = 0.0;
+            for obstacle in obstacles {
+                let (w, h) = obstacle_size(obstacle);
+                let hitbox = Hitbox {
+                    x: (
+                        x + x_offset + HITBOX_INSET,
+                        x + x_offset + w as f64 - HITBOX_INSET,
+                    ),
+                    y: (y + HITBOX_INSET, y + h as f64 - HITBOX_INSET),
+                };
+                x_offset += w as f64;
+
+                //nothing after an earlier hit matters
+                let until = hit.unwrap_or(until);
+                let (enter, exit) = self.overlap_window(&hitbox, widest, physics);
+                if enter >= until {
+                    //obstacles are sorted, nothing further can be reached yet
+                    break 'map;
+                }
+                if exit > until && first_unpassed.is_none() {
+                    first_unpassed = Some(idx);
+                }
+
+                let from = enter.max(self.checked_until);
+                let to = exit.min(until);
+                if from < to {
+                    if let Some(t) = self.first_collision(&hitbox, from, to, physics) {
+                        hit = Some(t);
+                    }
+                }
+            }
+            idx += 1;
+        }
+
+        if let Some(t) = hit {
+            self.dead_at = Some(t);
+            self.checked_until = t;
+            return self.dead_at;
+        }
+        self.next_obstacle = first_unpassed.unwrap_or(idx);
+        self.checked_until = until;
+        None
+    }
+
+    /// Time window in which a dino `width` wide overlaps `hitbox` on the x axis.
+    fn overlap_window(
+        &self,
+        hitbox: &Hitbox,
+        width: f64,
+        physics: &SessionPhysicsConfig,
+    ) -> (f64, f64) {
+        let front = DINO_X_OFFSET + width - HITBOX_INSET;
+        let back = DINO_X_OFFSET + HITBOX_INSET;
+        (
+            time_at_x(hitbox.x.0 - front, physics),
+            time_at_x(hitbox.x.1 - back, physics),
+        )
+    }
+
+    fn first_collision(
+        &self,
+        hitbox: &Hitbox,
+        from: f64,
+        to: f64,
+        physics: &SessionPhysicsConfig,
+    ) -> Option<f64> {
+        let duration = jump_duration(physics);
+
+        //the dino's shape only changes at these points
+        let mut splits = vec![from, to];
+        for start in &self.jumps {
+            splits.extend([*start, start + duration]);
+        }
+        for (start, end) in &self.ducks {
+            splits.extend([*start, *end]);
+        }
+        splits.retain(|t| *t >= from && *t <= to);
+        splits.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        splits.dedup();
+
+        for segment in splits.windows(2) {
+            let (a, b) = (segment[0], segment[1]);
+            let mid = (a + b) / 2.0;
+            let jump_start = self.jump_start_at(mid, physics);
+            let ducking = jump_start.is_none() && self.is_ducking_at(mid);
+            let (w, h) = obstacle_size(if ducking { &DINO_DUCK } else { &DINO_RUN });
+            let (w, h) = (w as f64, h as f64 - 2.0 * HITBOX_INSET);
+
+            let (enter, exit) = self.overlap_window(hitbox, w, physics);
+            let (a, b) = (a.max(enter), b.min(exit));
+            if a >= b {
+                continue;
+            }
+
+            //the dino's bottom has to be in this range for the boxes to overlap
+            let (low, high) = (hitbox.y.0 - h, hitbox.y.1);
+            let overlaps = |t: f64| {
+                let y = Self::height_at(jump_start, t, physics) + HITBOX_INSET;
+                y > low && y < high
+            };
+
+            let mut candidates = vec![a];
+            if let Some(start) = jump_start {
+                for level in [low, high] {
+                    let (t1, t2) = math::jump_time_above_height(
+                        (level - HITBOX_INSET) as f32,
+                        physics.jump_vel,
+                        physics.gravity,
+                    );
+                    candidates.extend([start + t1 as f64, start + t2 as f64]);
+                }
+            }
+            candidates.retain(|t| t.is_finite() && *t >= a && *t < b);
+            candidates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            const EPSILON: f64 = 1e-9;
+            if let Some(t) = candidates
+                .into_iter()
+                .find(|t| overlaps(*t) || overlaps((*t + EPSILON).min(b)))
+            {
+                return Some(t);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+fn test_physics() -> SessionPhysicsConfig {
+    SessionPhysicsConfig {
+        initial_vel: 8.0,
+        x_acc: 0.3,
+        gravity: -60.0,
+        jump_vel: 15.0,
+    }
+}
+
+#[test]
+fn grounded_dino_hits_cactus() {
+    let physics = test_physics();
+    let map = vec![((10.0, 0.0), vec![Obstacle::CactusSmall1])];
+    let mut dino = DinoSim::new();
+
+    assert_eq!(dino.advance(0.5, &map, &physics), None);
+    let dead_at = dino.advance(5.0, &map, &physics).unwrap();
+
+    //dies when its front edge reaches the cactus
+    let front = x_at(dead_at, &physics) + DINO_X_OFFSET + obstacle_size(&DINO_RUN).0 as f64;
+    assert!((front - HITBOX_INSET - (10.0 + HITBOX_INSET)).abs() < 1e-6);
+    assert_eq!(dino.advance(10.0, &map, &physics), Some(dead_at));
+}
+
+#[test]
+fn jumping_clears_cactus() {
+    let physics = test_physics();
+    let map = vec![(
+        (10.0, 0.0),
+        vec![Obstacle::CactusBig1, Obstacle::CactusSmall1],
+    )];
+    let mut dino = DinoSim::new();
+
+    //take off a little before the cactus so the dino is at its highest above it
+    let apex = time_at_x(10.0 - DINO_X_OFFSET, &physics);
+    assert!(dino.jump(apex - jump_duration(&physics) / 2.0, &physics));
+    //already in the air
+    assert!(!dino.jump(apex, &physics));
+
+    assert_eq!(dino.advance(5.0, &map, &physics), None);
+}
+
+#[test]
+fn ducking_passes_under_bird() {
+    let physics = test_physics();
+    let map = vec![((10.0, 0.7), vec![Obstacle::Bird1])];
+
+    let mut runner = DinoSim::new();
+    assert!(runner.advance(5.0, &map, &physics).is_some());
+
+    let mut ducker = DinoSim::new();
+    ducker.duck_start(0.2);
+    ducker.duck_end(4.0);
+    assert_eq!(ducker.advance(5.0, &map, &physics), None);
+}
+
+#[test]
+fn hits_are_found_while_still_under_another_obstacle() {
+    let physics = test_physics();
+    //the dino runs under the bird and into the cactus before it has left the bird behind
+    let map = vec![
+        ((10.0, 2.0), vec![Obstacle::Bird1]),
+        ((10.3, 0.0), vec![Obstacle::CactusSmall1]),
+    ];
+    let front = DINO_X_OFFSET + obstacle_size(&DINO_RUN).0 as f64 - HITBOX_INSET;
+    let hit = time_at_x(10.3 + HITBOX_INSET - front, &physics);
+    let mut dino = DinoSim::new();
+
+    assert_eq!(dino.advance(hit - 0.01, &map, &physics), None);
+    let dead_at = dino.advance(hit + 0.01, &map, &physics).unwrap();
+    assert!((dead_at - hit).abs() < 1e-6);
+}
@@ -0,0 +1,311 @@
+//! Finished matches, kept after their sessions close so there's an all-time leaderboard and a
+//! history for every player.
+//!
+//! `FileStore` appends every match to a file as a line of JSON and reads them all back on start,
+//! `MemoryStore` forgets them once the server stops.
+
+use crate::session_exec::PlayerResult;
+use crate::validator::SessionPhysicsConfig;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+pub struct MatchRecord {
+    pub id: Uuid,
+    #[serde(rename = "sessionName")]
+    pub session_name: String,
+    #[serde(rename = "endedAt")]
+    pub ended_at: u64, //unix seconds
+    pub seed: u32,
+    pub rules: MatchRules,
+    pub results: Vec<PlayerResult>,
+    pub bots: Vec<String>, //usernames in `results` the server played, they aren't ranked
+    pub replay: Option<Uuid>,
+}
+
+/// The settings a match was played with.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+pub struct MatchRules {
+    pub physics: SessionPhysicsConfig,
+    pub elimination: bool,
+    #[serde(rename = "maxDuration")]
+    pub max_duration: u64, //seconds
+}
+
+/// A single run on the all-time leaderboard.
+#[derive(Serialize, Clone, PartialEq, Debug, JsonSchema)]
+pub struct ScoreEntry {
+    pub username: String,
+    pub score: u64,
+    pub seed: u32,
+    #[serde(rename = "matchId")]
+    pub match_id: Uuid,
+    #[serde(rename = "endedAt")]
+    pub ended_at: u64,
+}
+
+pub trait MatchStore {
+    fn record(&mut self, record: MatchRecord) -> io::Result<()>;
+
+    /// Best runs of every match, best first and `TOP_SCORES` at most. Flagged runs and bots
+    /// don't count.
+    fn top_scores(&self, limit: usize) -> Vec<ScoreEntry>;
+
+    /// Matches `username` played in, newest first.
+    fn history(&self, username: &str, limit: usize) -> Vec<MatchRecord>;
+
+    /// Newest first.
+    fn recent(&self, limit: usize) -> Vec<MatchRecord>;
+}
+
+/// `FileStore` at `path`, or a `MemoryStore` if it's empty.
+pub fn open(path: &str) -> io::Result<Box<dyn MatchStore>> {
+    if path.is_empty() {
+        return Ok(Box::<MemoryStore>::default());
+    }
+    Ok(Box::new(FileStore::open(path)?))
+}
+
+/// How many of the best runs the stores keep track of.
+pub const TOP_SCORES: usize = 100;
+
+#[derive(Default)]
+pub struct MemoryStore {
+    matches: Vec<MatchRecord>, //oldest first
+    top: Vec<ScoreEntry>,      //best first, kept as matches are recorded
+}
+
+impl MatchStore for MemoryStore {
+    fn record(&mut self, record: MatchRecord) -> io::Result<()> {
+        let runs = record
+            .results
+            .iter()
+            .filter(|result| !result.flagged && !record.bots.contains(&result.username));
+        for result in runs {
+            //ties go to whoever got there first
+            let rank = self.top.partition_point(|entry| {
+                entry.score > result.score
+                    || (entry.score == result.score && entry.ended_at <= record.ended_at)
+            });
+            if rank < TOP_SCORES {
+                let entry = ScoreEntry {
+                    username: result.username.clone(),
+                    score: result.score,
+                    seed: record.seed,
+                    match_id: record.id,
+                    ended_at: record.ended_at,
+                };
+                self.top.insert(rank, entry);
+                self.top.truncate(TOP_SCORES);
+            }
+        }
+        self.matches.push(record);
+        Ok(())
+    }
+
+    fn top_scores(&self, limit: usize) -> Vec<ScoreEntry> {
+        self.top.iter().take(limit).cloned().collect()
+    }
+
+    fn history(&self, username: &str, limit: usize) -> Vec<MatchRecord> {
+        self.matches
+            .iter()
+            .rev()
+            .filter(|record| !record.bots.iter().any(|bot| bot == username))
+            .filter(|record| {
+                record
+                    .results
+                    .iter()
+                    .any(|result| result.username == username)
+            })
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    fn recent(&self, limit: usize) -> Vec<MatchRecord> {
+        self.matches.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+/// An append-only log of matches, one JSON object per line.
+pub struct FileStore {
+    file: File,
+    memory: MemoryStore,
+}
+
+impl FileStore {
+    /// Reads every match recorded at `path` so far, creating the file if there's none. Lines
+    /// that don't parse, like one cut short by a crash, are skipped.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let log = std::fs::read_to_string(path)?;
+
+        let mut memory = MemoryStore::default();
+        for (i, line) in log.lines().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(record) => memory.record(record)?,
+                Err(err) => println!(
+                    "[storage] skipping line {} of `{}`: {}",
+                    i + 1,
+                    path.display(),
+                    err
+                ),
+            }
+        }
+        //the next match starts on a line of its own
+        if !log.is_empty() && !log.ends_with('\n') {
+            file.write_all(b"\n")?;
+        }
+
+        Ok(Self { file, memory })
+    }
+}
+
+impl MatchStore for FileStore {
+    fn record(&mut self, record: MatchRecord) -> io::Result<()> {
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        //a single write so a crash can't leave half a line in the middle of the file
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        self.memory.record(record)
+    }
+
+    fn top_scores(&self, limit: usize) -> Vec<ScoreEntry> {
+        self.memory.top_scores(limit)
+    }
+
+    fn history(&self, username: &str, limit: usize) -> Vec<MatchRecord> {
+        self.memory.history(username, limit)
+    }
+
+    fn recent(&self, limit: usize) -> Vec<MatchRecord> {
+        self.memory.recent(limit)
+    }
+}
+
+/// A match where players rank by score.
+#[cfg(test)]
+pub(crate) fn test_record(
+    ended_at: u64,
+    results: &[(&str, u64, bool)],
+    bots: &[&str],
+) -> MatchRecord {
+    MatchRecord {
+        id: Uuid::new_v4(),
+        session_name: "lunch".to_owned(),
+        ended_at,
+        seed: 1,
+        rules: MatchRules {
+            physics: SessionPhysicsConfig::default(),
+            elimination: false,
+            max_duration: 60,
+        },
+        results: results
+            .iter()
+            .map(|(username, score, flagged)| PlayerResult {
+                rank: 1 + results.iter().filter(|other| other.1 > *score).count() as u32,
+                username: (*username).to_owned(),
+                score: *score,
+                survival_time: 0.0,
+                distance: *score as f64,
+                survived: false,
+                flagged: *flagged,
+            })
+            .collect(),
+        bots: bots.iter().map(|bot| (*bot).to_owned()).collect(),
+        replay: None,
+    }
+}
+
+#[test]
+fn matches_are_queried_newest_and_best_first() {
+    let mut store = MemoryStore::default();
+    store
+        .record(test_record(
+            1,
+            &[("a", 50, false), ("Rex", 900, false)],
+            &["Rex"],
+        ))
+        .unwrap();
+    store
+        .record(test_record(2, &[("a", 70, false), ("b", 400, true)], &[]))
+        .unwrap();
+    store
+        .record(test_record(3, &[("b", 50, false)], &[]))
+        .unwrap();
+
+    let top: Vec<(String, u64)> = store
+        .top_scores(10)
+        .into_iter()
+        .map(|entry| (entry.username, entry.score))
+        .collect();
+    assert_eq!(
+        top,
+        [
+            ("a".to_owned(), 70),
+            ("a".to_owned(), 50),
+            ("b".to_owned(), 50)
+        ]
+    );
+    assert_eq!(store.top_scores(1).len(), 1);
+
+    let history: Vec<u64> = store.history("a", 10).iter().map(|r| r.ended_at).collect();
+    assert_eq!(history, [2, 1]);
+    assert!(store.history("Rex", 10).is_empty(), "bots have no history");
+    let recent: Vec<u64> = store.recent(2).iter().map(|r| r.ended_at).collect();
+    assert_eq!(recent, [3, 2]);
+}
+
+#[test]
+fn only_the_best_runs_are_kept() {
+    let mut store = MemoryStore::default();
+    for score in 0..TOP_SCORES as u64 + 10 {
+        store
+            .record(test_record(score, &[("a", score, false)], &[]))
+            .unwrap();
+    }
+    let top = store.top_scores(usize::MAX);
+    assert_eq!(top.len(), TOP_SCORES);
+    assert_eq!(top[0].score, TOP_SCORES as u64 + 9);
+    assert_eq!(top[TOP_SCORES - 1].score, 10);
+}
+
+#[test]
+fn file_store_keeps_matches_across_restarts() {
+    let path = std::env::temp_dir().join(format!("dino-matches-{}.jsonl", Uuid::new_v4()));
+
+    let mut store = FileStore::open(&path).unwrap();
+    store
+        .record(test_record(1, &[("a", 50, false)], &[]))
+        .unwrap();
+    store
+        .record(test_record(2, &[("b", 60, false)], &[]))
+        .unwrap();
+    drop(store);
+    //a line a crash cut short
+    OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(b"{\"id\":")
+        .unwrap();
+
+    let mut store = FileStore::open(&path).unwrap();
+    store
+        .record(test_record(3, &[("c", 10, false)], &[]))
+        .unwrap();
+    drop(store);
+
+    let store = FileStore::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let recent: Vec<u64> = store.recent(10).iter().map(|r| r.ended_at).collect();
+    assert_eq!(recent, [3, 2, 1]);
+    assert_eq!(store.top_scores(10)[0].username, "b");
+}
@@ -1,11 +1,16 @@
-use crate::obstacles::Obstacle;
-use crate::session::SessionStatus;
-use std::collections::HashMap;
-use std::error::Error;
-use std::time::Duration;
-use std::{rc::Rc, time::SystemTime};
+use crate::math;
+use crate::obstacles::{obstacle_size, TALLEST_CACTUS};
+use crate::simulation;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use uuid::Uuid;
 
+/// Snake case unlike the rest of the protocol, it's also the `[session.physics]` table of the
+/// config file where keys, and the env var and command line overrides made from them, are snake
+/// case. Stored matches and replays use these names too.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
 pub struct SessionPhysicsConfig {
     pub initial_vel: f32,
     pub x_acc: f32,
@@ -13,56 +18,99 @@ pub struct SessionPhysicsConfig {
     pub jump_vel: f32,
 }
 
+impl Default for SessionPhysicsConfig {
+    fn default() -> Self {
+        Self {
+            initial_vel: 8.0,
+            x_acc: 0.3,
+            gravity: -60.0,
+            jump_vel: 15.0,
+        }
+    }
+}
+
+impl SessionPhysicsConfig {
+    /// Limits on what a host can pick for their session. Outside of them the game is either
+    /// unplayable or the map generator can't place obstacles that can be jumped over.
+    pub fn check_bounds(&self) -> Result<(), &'static str> {
+        if !(1.0..=40.0).contains(&self.initial_vel) {
+            return Err("initial_vel must be between 1 and 40");
+        }
+        if !(0.0..=5.0).contains(&self.x_acc) {
+            return Err("x_acc must be between 0 and 5");
+        }
+        if !(-300.0..=-10.0).contains(&self.gravity) {
+            return Err("gravity must be between -300 and -10");
+        }
+        if !(5.0..=60.0).contains(&self.jump_vel) {
+            return Err("jump_vel must be between 5 and 60");
+        }
+        if math::jump_height(self.jump_vel, self.gravity) < obstacle_size(&TALLEST_CACTUS).1 * 1.2 {
+            return Err("jumps must be high enough to clear the tallest cactus");
+        }
+        Ok(())
+    }
+}
+
 pub struct AntiCheatConfig {
     pub physics: SessionPhysicsConfig,
-    pub map: Vec<((f64, f64), Vec<Obstacle>)>,
-    pub map_req_segment_length: usize,
+    /// Allowed difference between a reported position and the simulated one, on top of
+    /// `position_tolerance_ratio` times the simulated position.
+    pub position_tolerance: f64,
+    pub position_tolerance_ratio: f64,
+    /// How far (in ms) a client's clock may run ahead of the server's, or keep sending events
+    /// after its game ended.
+    pub latency_tolerance: u128,
+}
+
+/// What a session does to a player once they've run out of tolerated violations. Every level
+/// includes the ones before it.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AntiCheatAction {
+    Log,
+    Flag,
+    Notify,
+    Kick,
 }
 
 pub enum PlayerEvent {
-    PositionBroadcast {
-        x: f64,
-        y: f32,
-    },
-    Jump {
-        x: f64,
-        t: u128,
-    },
-    Duck {
-        x: f64,
-        t: u128,
-    },
-    GameOverBroadcast {
-        x: f64,
-        t: u128,
-    },
-    SpeedUp {
-        x: f64,
-        t: u128,
-        speed_up_factor: f32,
-    },
+    PositionBroadcast { x: f64, y: f32 },
+    Jump { x: f64, t: u128 },
+    Duck { x: f64, t: u128 },
+    GameOverBroadcast { x: f64, t: u128 },
+    ScoreClaim { x: f64, score: u64, t: u128 },
 }
 
-pub enum SessionEvent {
-    CountdownStart,
-    GameStart,
-    GameEnd,
+#[derive(Debug, PartialEq)]
+pub enum Violation {
+    PositionMismatch { reported: f64, expected: f64 },
+    ImpossibleHeight { y: f32 },
+    ScoreMismatch { reported: u64, expected: u64 },
+    InconsistentReports { reported: f64, broadcast: f64 },
+    TimestampInFuture { t: u128 },
+    EventAfterGameOver,
+}
+
+/// Events kept per player for `get_nearest_event`, older ones are dropped.
+const MAX_HISTORY: usize = 128;
+
+#[derive(Default)]
+struct PlayerHistory {
+    events: VecDeque<(u128, PlayerEvent)>, //ms into the game they were received at, oldest first
+    game_over_at: Option<u128>,
 }
 
 pub struct AntiCheat {
     config: AntiCheatConfig,
-    timers: HashMap<Uuid, Rc<(SystemTime, fn(&mut Self))>>,
-    players: HashMap<Uuid, Vec<(u128, PlayerEvent)>>,
-    session_events: Vec<SessionEvent>,
+    players: HashMap<Uuid, PlayerHistory>,
 }
 
 impl AntiCheat {
     pub fn new(config: AntiCheatConfig) -> Self {
         Self {
             config,
-            timers: HashMap::new(),
             players: HashMap::new(),
-            session_events: Vec::new(),
         }
     }
 
@@ -71,107 +119,255 @@ impl AntiCheat {
             return Err("Duplicate player");
         }
 
-        self.players.insert(id, Vec::new());
+        self.players.insert(id, PlayerHistory::default());
 
         Ok(())
     }
 
+    /// Records `event`, received `elapsed_time` ms into the game, and checks it against the
+    /// physics and the player's earlier events.
     pub fn register_player_event(
         &mut self,
         id: Uuid,
         event: PlayerEvent,
         elapsed_time: u128,
-    ) -> Result<(), &'static str> {
+    ) -> Result<Option<Violation>, &'static str> {
         if !self.players.contains_key(&id) {
             return Err("No such registered player");
         }
 
-        self.players
-            .get_mut(&id)
-            .unwrap()
-            .push((elapsed_time, event));
+        let violation = self.validate(&id, &event, elapsed_time);
 
-        Ok(())
+        let player = self.players.get_mut(&id).unwrap();
+        if let PlayerEvent::GameOverBroadcast { t, .. } = event {
+            player.game_over_at.get_or_insert(t);
+        }
+        if player.events.len() == MAX_HISTORY {
+            player.events.pop_front();
+        }
+        player.events.push_back((elapsed_time, event));
+
+        Ok(violation)
     }
 
-    fn set_timeout(&mut self, f: fn(&mut Self), duration: Duration) -> Uuid {
-        let id = Uuid::new_v4();
-        self.timers
-            .insert(id, Rc::new((SystemTime::now() + duration, f)));
+    fn validate(&self, id: &Uuid, event: &PlayerEvent, elapsed_time: u128) -> Option<Violation> {
+        if let Some(t) = self.players[id].game_over_at {
+            if elapsed_time > t + self.config.latency_tolerance {
+                return Some(Violation::EventAfterGameOver);
+            }
+        }
 
-        id
+        match *event {
+            PlayerEvent::PositionBroadcast { x, y } => {
+                let max_height =
+                    math::jump_height(self.config.physics.jump_vel, self.config.physics.gravity);
+                if y > max_height * 1.1 {
+                    return Some(Violation::ImpossibleHeight { y });
+                }
+                self.check_position(x, elapsed_time)
+            }
+            PlayerEvent::Jump { x, t }
+            | PlayerEvent::Duck { x, t }
+            | PlayerEvent::GameOverBroadcast { x, t } => self
+                .check_timestamp(t, elapsed_time)
+                .or_else(|| self.check_position(x, t)),
+            PlayerEvent::ScoreClaim { x, score, t } => {
+                if let Some(violation) = self.check_timestamp(t, elapsed_time) {
+                    return Some(violation);
+                }
+                let expected = simulation::x_at(t as f64 * 0.001, &self.config.physics);
+                if score as f64 > expected + self.tolerance(expected) {
+                    return Some(Violation::ScoreMismatch {
+                        reported: score,
+                        expected: expected.round() as u64,
+                    });
+                }
+                match self.get_nearest_event(t, id) {
+                    Some(PlayerEvent::PositionBroadcast { x: broadcast, .. })
+                        if (x - broadcast).abs() > self.tolerance(*broadcast) =>
+                    {
+                        Some(Violation::InconsistentReports {
+                            reported: x,
+                            broadcast: *broadcast,
+                        })
+                    }
+                    _ => self.check_position(x, t),
+                }
+            }
+        }
     }
 
-    fn exec_timers(&mut self) {
-        let timers = self.timers.clone();
-        for (id, rc) in timers {
-            if SystemTime::now() > rc.0 {
-                rc.1(self);
-                self.timers.remove(&id);
-            }
+    fn tolerance(&self, x: f64) -> f64 {
+        self.config.position_tolerance + x.abs() * self.config.position_tolerance_ratio
+    }
+
+    /// Checks `x` against where the dino could have been at `t`, or up to `latency_tolerance`
+    /// before that since reports are stamped with the time they're received at.
+    fn check_position(&self, x: f64, t: u128) -> Option<Violation> {
+        let x_at = |t: u128| simulation::x_at(t as f64 * 0.001, &self.config.physics);
+        let (low, high) = (
+            x_at(t.saturating_sub(self.config.latency_tolerance)),
+            x_at(t),
+        );
+
+        if x < low - self.tolerance(low) {
+            Some(Violation::PositionMismatch {
+                reported: x,
+                expected: low,
+            })
+        } else if x > high + self.tolerance(high) {
+            Some(Violation::PositionMismatch {
+                reported: x,
+                expected: high,
+            })
+        } else {
+            None
         }
     }
 
-    fn get_nearest_event(&self, timestamp: u128, player_id: &Uuid) -> Option<&PlayerEvent> {
-        let events = if let Some(evts) = self.players.get(player_id) {
-            evts
+    fn check_timestamp(&self, t: u128, elapsed_time: u128) -> Option<Violation> {
+        if t > elapsed_time + self.config.latency_tolerance {
+            Some(Violation::TimestampInFuture { t })
         } else {
-            return None;
+            None
+        }
+    }
+
+    /// The event received closest to `timestamp` (ms into the game).
+    fn get_nearest_event(&self, timestamp: u128, player_id: &Uuid) -> Option<&PlayerEvent> {
+        let events = &self.players.get(player_id)?.events;
+
+        //events are recorded in the order they're received, so they're sorted by time
+        let after = events.partition_point(|(t, _)| *t < timestamp);
+        let dist_to_idx = |idx: usize| {
+            let t = events[idx].0;
+            t.max(timestamp) - t.min(timestamp)
         };
 
-        let target = timestamp;
+        let nearest_idx = match (after.checked_sub(1), after < events.len()) {
+            (Some(before), true) if dist_to_idx(before) <= dist_to_idx(after) => before,
+            (_, true) => after,
+            (Some(before), false) => before,
+            (None, false) => return None,
+        };
 
-        let mut start = 0usize;
-        let mut end = events.len();
-        let find_middle = |start, end| f64::floor((start as f64 + end as f64) / 2.0) as usize;
-        let mut middle = find_middle(start, end);
+        Some(&events[nearest_idx].1)
+    }
+}
 
-        let mut found = false;
+#[cfg(test)]
+fn test_anti_cheat() -> (AntiCheat, Uuid) {
+    let mut anti_cheat = AntiCheat::new(AntiCheatConfig {
+        physics: SessionPhysicsConfig {
+            initial_vel: 8.0,
+            x_acc: 0.3,
+            gravity: -60.0,
+            jump_vel: 15.0,
+        },
+        position_tolerance: 3.0,
+        position_tolerance_ratio: 0.05,
+        latency_tolerance: 1000,
+    });
+    let id = Uuid::new_v4();
+    anti_cheat.register_player(id).unwrap();
+    (anti_cheat, id)
+}
 
-        let mut nearest_dist = u128::MAX;
-        let mut nearest_idx = Option::<usize>::None;
+#[test]
+fn nearest_event() {
+    let (mut anti_cheat, id) = test_anti_cheat();
+    assert!(anti_cheat.get_nearest_event(0, &id).is_none());
 
-        let dist_to_idx = |v1: u128, idx: usize| {
-            let v2 = events[idx].0;
-            v1.max(v2) - v1.min(v2)
-        };
-        loop {
-            if start > end {
-                return None;
-            }
-            let middle_timestamp = events[middle].0;
-            //prevents underflow
-            nearest_dist = dist_to_idx(timestamp, middle);
-            nearest_idx = Some(middle);
+    for (t, x) in [(1000, 8.0), (2000, 16.6), (3000, 25.0)] {
+        let event = PlayerEvent::PositionBroadcast { x, y: 0.0 };
+        anti_cheat.register_player_event(id, event, t).unwrap();
+    }
 
-            if middle_timestamp == timestamp {
-                break;
-            }
+    let nearest_x = |t| match anti_cheat.get_nearest_event(t, &id) {
+        Some(PlayerEvent::PositionBroadcast { x, .. }) => *x,
+        _ => unreachable!(),
+    };
+    assert_eq!(nearest_x(0), 8.0);
+    assert_eq!(nearest_x(1600), 16.6);
+    assert_eq!(nearest_x(2400), 16.6);
+    assert_eq!(nearest_x(9000), 25.0);
+}
 
-            if timestamp > middle_timestamp {
-                start = middle + 1;
-                if dist_to_idx(timestamp, find_middle(start, end)) > nearest_dist {
-                    break;
-                }
-            } else if middle >= 1 {
-                end = middle - 1;
-                if dist_to_idx(timestamp, find_middle(start, end)) > nearest_dist {
-                    break;
-                }
-            } else {
-                break;
-            }
-        }
+#[test]
+fn catches_impossible_events() {
+    let (mut anti_cheat, id) = test_anti_cheat();
+    let mut register = |event, t| anti_cheat.register_player_event(id, event, t).unwrap();
 
-        if let Some(idx) = nearest_idx {
-            Some(&events[idx].1)
-        } else {
-            None
-        }
-    }
+    //x_at(10s) = 95
+    assert_eq!(
+        register(PlayerEvent::PositionBroadcast { x: 96.0, y: 1.0 }, 10_000),
+        None
+    );
+    assert!(matches!(
+        register(PlayerEvent::PositionBroadcast { x: 150.0, y: 0.0 }, 10_000),
+        Some(Violation::PositionMismatch { .. })
+    ));
+    assert!(matches!(
+        register(PlayerEvent::PositionBroadcast { x: 95.0, y: 4.0 }, 10_000),
+        Some(Violation::ImpossibleHeight { .. })
+    ));
+    assert!(matches!(
+        register(PlayerEvent::Jump { x: 95.0, t: 20_000 }, 10_000),
+        Some(Violation::TimestampInFuture { .. })
+    ));
+    assert!(matches!(
+        register(
+            PlayerEvent::ScoreClaim {
+                x: 95.0,
+                score: 500,
+                t: 10_000
+            },
+            10_000
+        ),
+        Some(Violation::ScoreMismatch { .. })
+    ));
+
+    register(
+        PlayerEvent::GameOverBroadcast { x: 96.0, t: 10_100 },
+        10_100,
+    );
+    assert_eq!(
+        register(PlayerEvent::PositionBroadcast { x: 110.0, y: 0.0 }, 12_000),
+        Some(Violation::EventAfterGameOver)
+    );
+}
+
+#[test]
+fn physics_bounds() {
+    let physics = SessionPhysicsConfig::default();
+    assert!(physics.check_bounds().is_ok());
+
+    let floaty = SessionPhysicsConfig {
+        gravity: -20.0,
+        jump_vel: 10.0,
+        ..physics
+    };
+    assert!(floaty.check_bounds().is_ok());
+
+    let low_jumps = SessionPhysicsConfig {
+        jump_vel: 6.0,
+        ..physics
+    };
+    assert!(low_jumps.check_bounds().is_err());
+
+    let nan = SessionPhysicsConfig {
+        x_acc: f32::NAN,
+        ..physics
+    };
+    assert!(nan.check_bounds().is_err());
+}
 
-    ///main loop of the game play validator!
-    pub fn run(&mut self) {
-        self.exec_timers()
+#[test]
+fn history_is_bounded() {
+    let (mut anti_cheat, id) = test_anti_cheat();
+    for t in 0..MAX_HISTORY as u128 * 2 {
+        let event = PlayerEvent::PositionBroadcast { x: 0.0, y: 0.0 };
+        anti_cheat.register_player_event(id, event, t).unwrap();
     }
+    assert_eq!(anti_cheat.players[&id].events.len(), MAX_HISTORY);
 }
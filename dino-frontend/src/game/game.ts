@@ -264,15 +264,16 @@ const main_ = async () => {
     const serverAddr = window.localStorage.getItem("server-addr");
     const sessionId = window.localStorage.getItem("session-id");
     const userId = window.localStorage.getItem("user-id");
+    const token = window.localStorage.getItem("user-token");
     const username = window.localStorage.getItem("username");
 
-    if (!serverAddr || !sessionId || !userId || !username) return;
+    if (!serverAddr || !sessionId || !userId || !token || !username) return;
 
     //=================================================================
     const server = new ServerBridge("ws://127.0.0.1:8080");
     server.initClient();
 
-    if (!(await server.login(sessionId, userId, username))) {
+    if (!(await server.login(sessionId, userId, token, username))) {
         alert(`Login to ${sessionId} as ${userId} failed`);
         return;
     }
@@ -0,0 +1,171 @@
+// Generated by `cargo run -- --emit-typescript` in dino-backend, don't edit by hand.
+// `TxData` is sent by the server, `RxData` by the client.
+
+export type BotLevel = "Easy" | "Medium" | "Hard";
+
+/** Why a request was refused. */
+export type ErrorCode =
+    | "UsernameTaken"
+    | "UsernameTooLong"
+    | "SessionFull"
+    | "SessionNotAcceptingPlayers"
+    | "UnknownSession"
+    | "Unauthorized"
+    | "MalformedMessage"
+    | "RateLimited"
+    | "AlreadyInSession"
+    | "ServerFull"
+    | "InvalidRules"
+    | "WrongPassword"
+    | "UnknownPlayer"
+    | "Banned"
+    | "GameNotOver"
+    | "UnknownReplay"
+    | "DailyChallengeDisabled";
+
+export type GameEvent =
+    | { type: "Jump"; pos: number }
+    | { type: "DuckStart"; pos: number }
+    | { type: "DuckEnd"; pos: number };
+
+/** Which recorded run a ghost replays. */
+export type GhostChoice =
+    | { type: "Best" }
+    | { type: "PersonalBest"; username: string };
+
+/** What a session's host can do to the lobby. */
+export type HostCommand =
+    | { type: "Kick"; username: string }
+    | { type: "Ban"; username: string }
+    | { type: "Lock"; locked: boolean }
+    | { type: "SetWaitTime"; seconds: number }
+    | { type: "TransferHost"; username: string }
+    | { type: "AddBot"; level: BotLevel };
+
+export type LeaderboardEntry = { rank: number; score: number; state: PlayerState; username: string };
+
+export type MatchRecord = { bots: string[]; endedAt: number; id: string; replay?: string | null; results: PlayerResult[]; rules: MatchRules; seed: number; sessionName: string };
+
+/** The settings a match was played with. */
+export type MatchRules = { elimination: boolean; maxDuration: number; physics: SessionPhysicsConfig };
+
+export type Obstacle =
+    | "Bird1"
+    | "Bird2"
+    | "CactusBig1"
+    | "CactusBig2"
+    | "CactusBigPair"
+    | "CactusBigSmall"
+    | "CactusSmall1"
+    | "CactusSmall2"
+    | "CactusSmall3"
+    | "CactusSmall4"
+    | "CactusSmall5"
+    | "CactusSmall6"
+    | "Cloud"
+    | "DinoDuck1"
+    | "DinoDuck2"
+    | "DinoGameOver1"
+    | "DinoGameOver2"
+    | "DinoJump"
+    | "DinoRun1"
+    | "DinoRun2"
+    | "Ground";
+
+export type PlayerMove = "None" | "Up" | "Down";
+
+export type PlayerResult = { distance: number; flagged: boolean; rank: number; score: number; survivalTime: number; survived: boolean; username: string };
+
+export type PlayerSnapshot = { connected: boolean; pos: [number, number]; score: number; username: string };
+
+export type PlayerState = "Alive" | "Dead" | "Disconnected";
+
+export type QueryResponseType =
+    | { type: "Sessions"; sessions: Array<[string, string, SessionStatusSimplified, Array<[string, number | null]>]> }
+    | { type: "LeaderBoard"; flagged: string[]; scores: LeaderboardEntry[]; sessionId: string }
+    | { type: "SessionStatus"; seed: number; status: string; time: number }
+    | { type: "DailyLeaderBoard"; date: string; scores: Array<[string, number]>; seed: number }
+    | { type: "TopScores"; scores: ScoreEntry[] }
+    | { type: "PlayerHistory"; matches: MatchRecord[]; username: string }
+    | { type: "RecentMatches"; matches: MatchRecord[] }
+    | { type: "Ratings"; players: RatedPlayer[] }
+    | { type: "RatingHistory"; history: RatingChange[]; rating?: number | null; username: string };
+
+export type QueryType =
+    | { type: "Sessions" }
+    | { type: "LeaderBoard"; sessionId: string }
+    | { type: "SessionStatus"; sessionId: string }
+    | { type: "DailyLeaderBoard"; date?: string | null }
+    | { type: "TopScores"; limit?: number | null }
+    | { type: "PlayerHistory"; limit?: number | null; username: string }
+    | { type: "RecentMatches"; limit?: number | null }
+    | { type: "Ratings"; limit?: number | null }
+    | { type: "RatingHistory"; limit?: number | null; username: string };
+
+export type RatedPlayer = { games: number; rank: number; rating: number; username: string };
+
+/** How a single match changed a player's rating. */
+export type RatingChange = { change: number; endedAt: number; matchId: string; rating: number };
+
+export type RxData =
+    | { type: "Query"; query: QueryType }
+    | { type: "CreateSession"; password?: string | null; rules?: SessionRules | null; seed?: number | null; sessionName: string; username: string; visibility?: SessionVisibility; waitTime: number }
+    | { type: "DailyChallenge"; username: string }
+    | { type: "CreateUser"; password?: string | null; sessionId: string; username: string }
+    | { type: "JoinWithCode"; joinCode: string; password?: string | null; username: string }
+    | { type: "Login"; sessionId: string; token: string }
+    | { type: "LaunchGame"; sessionId: string; token: string }
+    | { type: "Rematch"; sameMap?: boolean; sessionId: string; token: string }
+    | { type: "HostCommand"; command: HostCommand; sessionId: string; token: string }
+    | { type: "WatchReplay"; replayId: string; speed?: number | null }
+    | { type: "QuickPlay"; username: string }
+    | { type: "LeaveQueue" }
+    | { type: "Spectate"; password?: string | null; sessionId: string }
+    | { type: "BroadcastReq"; pos: [number, number]; tick: number }
+    | { type: "ValidationData"; moveDir?: PlayerMove | null; posX: number; score: number; sessionId: string; timestamp: number; userId: string }
+    | { type: "Map"; index: number; sessionId: string; userId: string }
+    | { type: "GameEvent"; event: GameEvent; userId: string }
+    | { type: "Event"; code: number; pos: [number, number]; timestamp: number; vel: [number, number] }
+    | { type: "GameOver"; sessionId: string; token: string };
+
+/** A single run on the all-time leaderboard. */
+export type ScoreEntry = { endedAt: number; matchId: string; score: number; seed: number; username: string };
+
+/** Snake case unlike the rest of the protocol, it's also the `[session.physics]` table of the config file where keys, and the env var and command line overrides made from them, are snake case. Stored matches and replays use these names too. */
+export type SessionPhysicsConfig = { gravity?: number; initial_vel?: number; jump_vel?: number; x_acc?: number };
+
+/** Gameplay settings a host can pick for their session, anything left out is the server's. */
+export type SessionRules = { countdown?: number | null; elimination?: boolean | null; ghosts?: GhostChoice[]; maxDuration?: number | null; physics?: SessionPhysicsConfig | null };
+
+export type SessionStatusSimplified = "Waiting" | "Busy" | "Ended";
+
+/** Who can find and join a session. */
+export type SessionVisibility = "Public" | "Unlisted" | "Private";
+
+export type TxData =
+    | { type: "QueryResponse"; queryRes: QueryResponseType }
+    | { type: "SessionCreationResponse"; creationSucceeded: boolean; joinCode?: string | null; seed?: number | null; sessionId?: string | null }
+    | { type: "UserCreationResponse"; creationSucceeded: boolean; token?: string | null; userId?: string | null }
+    | { type: "LoginResponse"; succeeded: boolean; token?: string | null }
+    | { type: "SpectateResponse"; succeeded: boolean }
+    | { type: "WatchReplayResponse"; succeeded: boolean }
+    | { type: "Leaderboard"; leaderboard: LeaderboardEntry[] }
+    | { type: "GameResults"; replay?: string | null; results: PlayerResult[] }
+    | { type: "GameEnded"; rematchWindow: number }
+    | { type: "RematchVote"; needed: number; username: string; votes: number }
+    | { type: "RematchStart"; seed: number; waitTime: number }
+    | { type: "QuickPlayResponse"; queued: boolean }
+    | { type: "MatchFound"; seed: number; sessionId: string }
+    | { type: "Broadcast"; ghost: boolean; pos: [number, number]; tick: number; username: string }
+    | { type: "GameCountdownStart"; duration: number; physics: SessionPhysicsConfig }
+    | { type: "GameStart" }
+    | { type: "Map"; map: Array<[[number, number], Obstacle[]]> }
+    | { type: "UserGameOverBroadcast"; ghost: boolean; score: number; username: string }
+    | { type: "UserGameOver"; score: number; userId: string }
+    | { type: "GameEvent"; event: GameEvent; ghost: boolean; username: string }
+    | { type: "Event"; code: number; ghost: boolean; pos: [number, number]; timestamp: number; username: string; vel: [number, number] }
+    | { type: "InvalidationNotice" }
+    | { type: "Resync"; elapsed?: number | null; leaderboard: LeaderboardEntry[]; map: Array<[[number, number], Obstacle[]]>; players: PlayerSnapshot[]; status: string; time: number }
+    | { type: "HostChanged"; username: string }
+    | { type: "HostAction"; command: HostCommand; host: string }
+    | { type: "Error"; code: ErrorCode; request?: string | null };
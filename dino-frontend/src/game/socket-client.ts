@@ -1,5 +1,6 @@
-import { serialize, deserialize, GameEvent } from "./ws-de-serialize";
-import type { TxData, RxData } from "./ws-de-serialize";
+import { serialize, deserialize } from "./ws-de-serialize";
+import type { TxData, RxData, Physics, GameEvent } from "./ws-de-serialize";
+import type { SessionStatusSimplified } from "./protocol";
 import Game from "../components/Game.svelte";
 
 class SocketClient {
@@ -101,6 +102,14 @@ class SocketClient {
     }
 }
 
+//id, name, status and the players with their ratings, null until their first rated match
+type SessionListing = [
+    string,
+    string,
+    SessionStatusSimplified,
+    Array<[string, number | null]>
+];
+
 interface MapData {
     mapIdx: number;
     mapRequestSent: boolean;
@@ -136,6 +145,7 @@ interface GameData {
 
     userId?: string;
     sessionId?: string;
+    token?: string; //resume token, replaced on every login
 
     userName?: string;
     sessionName?: string;
@@ -172,11 +182,9 @@ class ServerBridge {
         this.broadcastBuffer = [];
     }
 
-    getSessionList(): Promise<Array<[string, string, string, Array<string>]>> {
+    getSessionList(): Promise<Array<SessionListing>> {
         const getSessionList = (
-            onRcv: (
-                data: Array<[string, string, string, Array<string>]>
-            ) => void
+            onRcv: (data: Array<SessionListing>) => void
         ) => {
             this.socketClient?.send({
                 type: "Query",
@@ -243,6 +251,7 @@ class ServerBridge {
                 }
                 if (msg.type === "UserCreationResponse") {
                     this.gameData.userId = msg.userId as string;
+                    this.gameData.token = msg.token as string;
                 }
 
                 if (this.gameData.sessionId && this.gameData.userId) {
@@ -268,6 +277,7 @@ class ServerBridge {
             onJoin: (
                 username: string,
                 userId: string,
+                token: string,
                 sessionId: string
             ) => void,
             onUnable: () => void = () => {}
@@ -282,15 +292,21 @@ class ServerBridge {
                 this.socketClient?.deleteMsgCaller(callerIdx as number);
                 if (msg.creationSucceeded === true) {
                     this.gameData.state = "Waiting";
-                    onJoin(username, msg.userId as string, sessionId);
+                    onJoin(
+                        username,
+                        msg.userId as string,
+                        msg.token as string,
+                        sessionId
+                    );
                 } else onUnable();
             });
         };
         return new Promise((resolve, reject) => {
             this.callOnOpenSocket(() => {
-                joinSession((username, userId, sessionId) => {
+                joinSession((username, userId, token, sessionId) => {
                     this.gameData.userName = username;
                     this.gameData.userId = userId;
+                    this.gameData.token = token;
                     this.gameData.sessionId = sessionId;
                     resolve();
                 }, reject);
@@ -301,13 +317,14 @@ class ServerBridge {
     login(
         sessionId: string,
         userId: string,
+        token: string,
         username: string
     ): Promise<boolean> {
         const login = (
             onLogin: (succeeded: boolean) => void,
             onErr: () => void
         ) => {
-            this.socketClient?.send({ type: "Login", sessionId, userId });
+            this.socketClient?.send({ type: "Login", sessionId, token });
             const callerIdx = this.socketClient?.onMessage((msg) => {
                 if (msg.type !== "LoginResponse") return;
                 this.socketClient?.deleteMsgCaller(callerIdx as number);
@@ -317,6 +334,9 @@ class ServerBridge {
                     this.gameData.userName = username;
                     this.gameData.userId = userId;
                     this.gameData.sessionId = sessionId;
+                    //the old token is revoked, a reload has to log in with this one
+                    this.gameData.token = msg.token ?? token;
+                    window.localStorage.setItem("user-token", this.gameData.token);
                     onLogin(msg.succeeded);
                 } else {
                     onErr();
@@ -329,22 +349,24 @@ class ServerBridge {
         });
     }
 
-    onCountdownStart(fn: (duration: number) => void): Promise<void> {
+    onCountdownStart(
+        fn: (duration: number, physics: Physics) => void
+    ): Promise<void> {
         const listenForCountdownStart = (
-            onRecv: (duration: number) => void
+            onRecv: (duration: number, physics: Physics) => void
         ) => {
             const callerIdx = this.socketClient?.onMessage((msg) => {
                 if (msg.type !== "GameCountdownStart") return;
                 this.socketClient?.deleteMsgCaller(callerIdx as number);
                 this.gameData.countdownDuration = msg.duration;
                 this.gameData.state = "Countdown";
-                onRecv(msg.duration);
+                onRecv(msg.duration, msg.physics);
             });
         };
 
         return new Promise((resolve, _reject) => {
-            listenForCountdownStart((duration) => {
-                fn(duration);
+            listenForCountdownStart((duration, physics) => {
+                fn(duration, physics);
                 resolve();
             });
         });
@@ -356,6 +378,7 @@ class ServerBridge {
                 if (msg.type !== "GameStart") return;
                 this.socketClient?.deleteMsgCaller(callerIdx as number);
                 this.gameData.state = "Active";
+                this.gameData.startTime = new Date();
                 onRecv();
             });
         };
@@ -373,7 +396,7 @@ class ServerBridge {
             this.socketClient?.send({
                 type: "LaunchGame",
                 sessionId: this.gameData.sessionId || "",
-                userId: this.gameData.userId || "",
+                token: this.gameData.token || "",
             });
         };
         this.callOnOpenSocket(requestGameLaunch);
@@ -383,9 +406,8 @@ class ServerBridge {
     //MOST TIME SENSITIVE function
     broadcastData(relYPos: number, relXPos: number) {
         (this.socketClient as SocketClient).send({
-            type: "BroadcastRequest",
-            posY: relYPos,
-            posX: relXPos,
+            type: "BroadcastReq",
+            pos: [relXPos, relYPos],
             tick: this.tick,
         });
         this.tick++;
@@ -393,16 +415,22 @@ class ServerBridge {
 
     onRecvGameEvet(fn: (username: string, event: GameEvent) => void) {
         this.socketClient!.onMessage((msg) => {
-            if (msg.type != "GameEvent") return;
+            //ghosts share usernames with players and aren't drawn yet
+            if (msg.type != "GameEvent" || msg.ghost) return;
             fn(msg.username, msg.event);
         });
     }
 
+    //checked by the server's anti cheat against where the dino could be by now
     broadcastUpdateEvt(relXPos: number, score: number) {
+        if (!this.gameData.startTime) return;
         this.socketClient!.send({
-            type: "GameEvent",
+            type: "ValidationData",
+            sessionId: this.gameData.sessionId!,
             userId: this.gameData.userId!,
-            event: { type: "StatusUpdate", pos: relXPos, score },
+            posX: relXPos,
+            score,
+            timestamp: new Date().getTime() - this.gameData.startTime.getTime(),
         });
     }
 
@@ -418,8 +446,8 @@ class ServerBridge {
         fn: (username: string, posX: number, posY: number) => void
     ) {
         (this.socketClient as SocketClient).onMessage((msg) => {
-            if (msg.type !== "PlayerDataBroadcast") return;
-            fn(msg.username, msg.posX, msg.posY);
+            if (msg.type !== "Broadcast" || msg.ghost) return;
+            fn(msg.username, msg.pos[0], msg.pos[1]);
         });
     }
 
@@ -439,13 +467,13 @@ class ServerBridge {
         (this.socketClient as SocketClient).send({
             type: "GameOver",
             sessionId: this.gameData.sessionId as string,
-            userId: this.gameData.userId as string,
+            token: this.gameData.token as string,
         });
     }
 
     onRecvGameOver(fn: (username: string, score: number) => void) {
         (this.socketClient as SocketClient).onMessage((msg) => {
-            if (msg.type !== "UserGameOverBroadcast") return;
+            if (msg.type !== "UserGameOverBroadcast" || msg.ghost) return;
             fn(msg.username, msg.score);
         });
     }
@@ -570,11 +598,13 @@ class ServerBridge {
                 if (
                     this.gameData.userId &&
                     this.gameData.sessionId &&
+                    this.gameData.token &&
                     this.gameData.userName
                 ) {
                     this.login(
                         this.gameData.sessionId,
                         this.gameData.userId,
+                        this.gameData.token,
                         this.gameData.userName
                     )
                         .catch((_) => alert("Login failed :("))
@@ -114,17 +114,20 @@ export const activeGameState: GameStateBuilderData = {
     onEnter: function (sres: StateResourceType, gres: GlobalGameResources) {
         console.log(`Entering ${this.state} state`);
 
-        sres.jumpVel = gres.unitLength * 15;
-        sres.gravity = gres.unitLength * -60;
+        //the defaults until the session sends its own on countdown
+        let physics = { initial_vel: 8, x_acc: 0.3, gravity: -60, jump_vel: 15 };
 
-        sres.acc.x = gres.unitLength * 0.3;
-        sres.vel.x = gres.unitLength * 8;
+        sres.jumpVel = gres.unitLength * physics.jump_vel;
+        sres.gravity = gres.unitLength * physics.gravity;
+
+        sres.acc.x = gres.unitLength * physics.x_acc;
+        sres.vel.x = gres.unitLength * physics.initial_vel;
         window.addEventListener("recalc-responsive", function () {
-            sres.jumpVel = gres.unitLength * 15;
-            sres.gravity = gres.unitLength * -60;
+            sres.jumpVel = gres.unitLength * physics.jump_vel;
+            sres.gravity = gres.unitLength * physics.gravity;
 
-            sres.acc.x = gres.unitLength * 0.3;
-            // sres.vel.x = gres.unitLength * 8
+            sres.acc.x = gres.unitLength * physics.x_acc;
+            // sres.vel.x = gres.unitLength * physics.initial_vel
         });
 
         function startCountdown(duration: number) {
@@ -155,9 +158,16 @@ export const activeGameState: GameStateBuilderData = {
             );
         }
 
-        gres.server.onCountdownStart(function (duration) {
+        gres.server.onCountdownStart(function (duration, sessionPhysics) {
             gres.renderer.removeRenderObject("info-text", 5);
 
+            //the server sends every field, they're optional for the rules a host asks for
+            physics = { ...physics, ...sessionPhysics };
+            sres.jumpVel = gres.unitLength * physics.jump_vel;
+            sres.gravity = gres.unitLength * physics.gravity;
+            sres.acc.x = gres.unitLength * physics.x_acc;
+            sres.vel.x = gres.unitLength * physics.initial_vel;
+
             startCountdown(duration - 1);
         });
         gres.server.onGameStart(function () {
@@ -553,7 +563,7 @@ export const activeGameState: GameStateBuilderData = {
         gres.server.socketClient?.onMessage(() => {});
 
         gres.server.socketClient?.onMessage((msg) => {
-            if (sres.startTime <= -1 || msg.type !== "Event") return;
+            if (sres.startTime <= -1 || msg.type !== "Event" || msg.ghost) return;
 
             msg.pos = [msg.pos[0] * gres.unitLength, msg.pos[1] * gres.unitLength]
             msg.vel = [msg.vel[0] * gres.unitLength, msg.vel[1] * gres.unitLength]
@@ -107,9 +107,10 @@ export const initialGameState: GameStateBuilderData = {
         const serverAddr = window.localStorage.getItem("server-addr");
         const sessionId = window.localStorage.getItem("session-id");
         const userId = window.localStorage.getItem("user-id");
+        const token = window.localStorage.getItem("user-token");
         const username = window.localStorage.getItem("username");
 
-        if (!serverAddr || !sessionId || !userId || !username) {
+        if (!serverAddr || !sessionId || !userId || !token || !username) {
             sres.infoText = "Credentials not found :(";
             sres.progressIndicatorStatus = "failed";
             return;
@@ -119,7 +120,7 @@ export const initialGameState: GameStateBuilderData = {
             if (afterConnectingRan) return;
             //executed once
 
-            const loginPromise = gres.server.login(sessionId, userId, username);
+            const loginPromise = gres.server.login(sessionId, userId, token, username);
             sres.infoText = "Logging in...";
 
             //======setup global state==========
@@ -1,80 +1,11 @@
-type QueryResponse =
-    | { type: "Sessions"; sessions: Array<[string, string, string, [string]]> }
-    | {
-          type: "LeaderBoard";
-          sessionId: string;
-          scores: Array<[string, number]>;
-      }
-    | { type: "None" }
-    | { type: "SessionStatus"; status: string; time: number };
+import type * as protocol from "./protocol";
 
-type RxData =
-    | { type: "QueryResponse"; queryRes: QueryResponse }
-    | {
-          type: "SessionCreationResponse";
-          creationSucceeded: boolean;
-          sessionId?: string;
-      }
-    | {
-          type: "UserCreationResponse";
-          creationSucceeded: boolean;
-          userId?: string;
-      }
-    | {
-          type: "Broadcast";
-          username: string;
-          pos: [number, number],
-          tick: number;
-      }
-    | { type: "LoginResponse"; succeeded: boolean }
-    | { type: "GameCountdownStart"; duration: number }
-    | { type: "GameStart" }
-    | { type: "Map"; map: [[[number, number], [any]]] }
-    | { type: "UserGameOverBroadcast"; username: string; score: number }
-    | { type: "UserGameOver"; score: number; userId: string }
-    | { type: "InvalidationNotice" }
-    | { type: "GameEvent"; username: string; event: GameEvent }
-    | { type: "Event", username: string, code: number, timestamp: number, pos: [number, number], vel: [number, number] }
-    | { type: "None" };
+// protocol.ts is named from the server's side, what it sends is what we receive
+type RxData = protocol.TxData | { type: "None" };
+type TxData = protocol.RxData;
+type QueryResponse = protocol.QueryResponseType | { type: "None" };
 
-type QueryType =
-    | { type: "Sessions" }
-    | { type: "LeaderBoard"; sessionId: string }
-    | { type: "SessionStatus"; sessionId: string };
-
-type MoveDir = "None" | "Up" | "Down";
-
-type GameEvent =
-    | { type: "StatusUpdate"; pos: number; score: number }
-    | { type: "Jump"; pos: number }
-    | { type: "DuckStart"; pos: number }
-    | { type: "DuckEnd"; pos: number };
-
-type TxData =
-    | { type: "Query"; query: QueryType }
-    | { type: "CreateSession"; username: string; sessionName: string }
-    | { type: "CreateUser"; sessionId: string; username: string }
-    | { type: "LaunchGame"; sessionId: string; userId: string }
-    | {
-          type: "BroadcastReq";
-          pos: [number, number],
-          tick: number;
-      }
-    | {
-          type: "ValidationData";
-          sessionId: string;
-          userId: string;
-          posX: number;
-          score: number;
-          timestamp: number;
-          moveDir: MoveDir;
-      }
-    | { type: "GameEvent"; userId: string; event: GameEvent }
-    | { type: "Map"; sessionId: string; userId: string; index: number }
-    | { type: "Login"; sessionId: string; userId: string }
-    | { type: "GameEvent"; userId: string; event: GameEvent }
-    | { type: "Event", timestamp: number, code: number, vel: [number, number], pos: [number, number]}
-    | { type: "GameOver"; sessionId: string; userId: string };
+type Physics = protocol.SessionPhysicsConfig;
 
 function deserialize(jsonStr: string): RxData {
     let json = JSON.parse(jsonStr);
@@ -87,6 +18,7 @@ function deserialize(jsonStr: string): RxData {
                 username: json["username"],
                 pos: json["pos"],
                 tick: json["tick"],
+                ghost: json["ghost"],
             };
     }
     // hopefully theres a less verbose way...
@@ -100,6 +32,7 @@ function deserialize(jsonStr: string): RxData {
                 pos: json["pos"],
                 vel: json["vel"],
                 timestamp: json["timestamp"],
+                ghost: json["ghost"],
             } as RxData;
         case "GameEvent": {
             if (!validateKeys(json, { username: "", event: {} }))
@@ -108,6 +41,7 @@ function deserialize(jsonStr: string): RxData {
                 type: "GameEvent",
                 username: json["username"],
                 event: json["event"],
+                ghost: json["ghost"],
             } as RxData;
         }
         case "QueryResponse":
@@ -124,8 +58,11 @@ function deserialize(jsonStr: string): RxData {
                     type: "SessionCreationResponse",
                     creationSucceeded: json["creationSucceeded"],
                 };
-                if (dezerd.creationSucceeded)
+                if (dezerd.creationSucceeded) {
                     dezerd.sessionId = json["sessionId"];
+                    dezerd.seed = json["seed"];
+                    dezerd.joinCode = json["joinCode"];
+                }
                 return dezerd;
             }
         case "UserCreationResponse":
@@ -136,13 +73,21 @@ function deserialize(jsonStr: string): RxData {
                     type: "UserCreationResponse",
                     creationSucceeded: json["creationSucceeded"],
                 };
-                if (dezerd.creationSucceeded) dezerd.userId = json["userId"];
+                if (dezerd.creationSucceeded) {
+                    dezerd.userId = json["userId"];
+                    dezerd.token = json["token"];
+                }
                 return dezerd;
             }
      
         case "GameCountdownStart":
-            if (!validateKeys(json, { duration: 0 })) return { type: "None" };
-            return { type: "GameCountdownStart", duration: json["duration"] };
+            if (!validateKeys(json, { duration: 0, physics: {} }))
+                return { type: "None" };
+            return {
+                type: "GameCountdownStart",
+                duration: json["duration"],
+                physics: json["physics"],
+            };
         case "GameStart":
             return { type: "GameStart" };
         case "Map":
@@ -155,6 +100,7 @@ function deserialize(jsonStr: string): RxData {
                 type: "UserGameOverBroadcast",
                 username: json["username"],
                 score: json["score"],
+                ghost: json["ghost"],
             };
         case "LoginResponse":
             if (!validateKeys(json, { succeeded: true }))
@@ -162,6 +108,64 @@ function deserialize(jsonStr: string): RxData {
             return {
                 type: "LoginResponse",
                 succeeded: json["succeeded"],
+                token: json["token"] ?? undefined,
+            };
+        case "SpectateResponse":
+            if (!validateKeys(json, { succeeded: true }))
+                return { type: "None" };
+            return {
+                type: "SpectateResponse",
+                succeeded: json["succeeded"],
+            };
+        case "Leaderboard":
+            if (!validateKeys(json, { leaderboard: [] }))
+                return { type: "None" };
+            return { type: "Leaderboard", leaderboard: json["leaderboard"] };
+        case "GameResults":
+            if (!validateKeys(json, { results: [] })) return { type: "None" };
+            return {
+                type: "GameResults",
+                results: json["results"],
+                replay: json["replay"] ?? undefined,
+            };
+        case "GameEnded":
+            if (!validateKeys(json, { rematchWindow: 0 }))
+                return { type: "None" };
+            return { type: "GameEnded", rematchWindow: json["rematchWindow"] };
+        case "RematchVote":
+            if (!validateKeys(json, { username: "", votes: 0, needed: 0 }))
+                return { type: "None" };
+            return {
+                type: "RematchVote",
+                username: json["username"],
+                votes: json["votes"],
+                needed: json["needed"],
+            };
+        case "RematchStart":
+            if (!validateKeys(json, { seed: 0, waitTime: 0 }))
+                return { type: "None" };
+            return {
+                type: "RematchStart",
+                seed: json["seed"],
+                waitTime: json["waitTime"],
+            };
+        case "WatchReplayResponse":
+            if (!validateKeys(json, { succeeded: true }))
+                return { type: "None" };
+            return {
+                type: "WatchReplayResponse",
+                succeeded: json["succeeded"],
+            };
+        case "QuickPlayResponse":
+            if (!validateKeys(json, { queued: true })) return { type: "None" };
+            return { type: "QuickPlayResponse", queued: json["queued"] };
+        case "MatchFound":
+            if (!validateKeys(json, { sessionId: "", seed: 0 }))
+                return { type: "None" };
+            return {
+                type: "MatchFound",
+                sessionId: json["sessionId"],
+                seed: json["seed"],
             };
         case "UserGameOver":
             if (!validateKeys(json, { score: 0, userId: "" }))
@@ -173,6 +177,36 @@ function deserialize(jsonStr: string): RxData {
             };
         case "InvalidationNotice":
             return { type: "InvalidationNotice" };
+        case "Resync":
+            if (!validateKeys(json, { status: "", time: 0, map: [], leaderboard: [], players: [] }))
+                return { type: "None" };
+            return {
+                type: "Resync",
+                status: json["status"],
+                time: json["time"],
+                elapsed: json["elapsed"] ?? undefined,
+                map: json["map"],
+                leaderboard: json["leaderboard"],
+                players: json["players"],
+            };
+        case "HostChanged":
+            if (!validateKeys(json, { username: "" })) return { type: "None" };
+            return { type: "HostChanged", username: json["username"] };
+        case "HostAction":
+            if (!validateKeys(json, { host: "", command: {} }))
+                return { type: "None" };
+            return {
+                type: "HostAction",
+                host: json["host"],
+                command: json["command"],
+            };
+        case "Error":
+            if (!validateKeys(json, { code: "" })) return { type: "None" };
+            return {
+                type: "Error",
+                code: json["code"],
+                request: json["request"] ?? undefined,
+            };
         default:
             return { type: "None" };
     }
@@ -183,7 +217,7 @@ function parseQueryResponse(json: any): QueryResponse {
  
         case "SessionStatus":
             if(!validateKeys(json, {status: '', time: 0})) return { type: "None" };
-            return { type: "SessionStatus", status: json["status"], time: json["t"]}
+            return { type: "SessionStatus", status: json["status"], time: json["time"], seed: json["seed"]}
         case "Sessions":
             if (!validateKeys(json, { sessions: [] })) return { type: "None" };
             return { type: "Sessions", sessions: json["sessions"] };
@@ -194,6 +228,42 @@ function parseQueryResponse(json: any): QueryResponse {
                 type: "LeaderBoard",
                 sessionId: json["sessionId"],
                 scores: json["scores"],
+                flagged: json["flagged"] ?? [],
+            };
+        case "DailyLeaderBoard":
+            if (!validateKeys(json, { date: "", seed: 0, scores: [] }))
+                return { type: "None" };
+            return {
+                type: "DailyLeaderBoard",
+                date: json["date"],
+                seed: json["seed"],
+                scores: json["scores"],
+            };
+        case "TopScores":
+            if (!validateKeys(json, { scores: [] })) return { type: "None" };
+            return { type: "TopScores", scores: json["scores"] };
+        case "PlayerHistory":
+            if (!validateKeys(json, { username: "", matches: [] }))
+                return { type: "None" };
+            return {
+                type: "PlayerHistory",
+                username: json["username"],
+                matches: json["matches"],
+            };
+        case "RecentMatches":
+            if (!validateKeys(json, { matches: [] })) return { type: "None" };
+            return { type: "RecentMatches", matches: json["matches"] };
+        case "Ratings":
+            if (!validateKeys(json, { players: [] })) return { type: "None" };
+            return { type: "Ratings", players: json["players"] };
+        case "RatingHistory":
+            if (!validateKeys(json, { username: "", history: [] }))
+                return { type: "None" };
+            return {
+                type: "RatingHistory",
+                username: json["username"],
+                rating: json["rating"] ?? null,
+                history: json["history"],
             };
         default:
             return { type: "None" };
@@ -231,5 +301,18 @@ function validateKeys(msg: any, ref: any): boolean {
     return validated;
 }
 
-export type { RxData, TxData, GameEvent };
+export type { RxData, TxData, Physics };
+export type {
+    GameEvent,
+    ErrorCode,
+    PlayerSnapshot,
+    PlayerResult,
+    LeaderboardEntry,
+    HostCommand,
+    BotLevel,
+    ScoreEntry,
+    MatchRecord,
+    RatedPlayer,
+    RatingChange,
+} from "./protocol";
 export { serialize, deserialize };